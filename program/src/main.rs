@@ -16,6 +16,9 @@ use crate::modules::{BufferSceneInfo, SceneBuilder};
 const WIDTH: usize = 640 * 2;
 const HEIGHT: usize = 360 * 2;
 
+//rendering the camera path into numbered images
+const SEQUENCE_DIRECTORY: &str = "sequence";
+
 pub fn main() {
     pretty_env_logger::init();
 
@@ -28,11 +31,33 @@ pub fn main() {
         canvas_width: WIDTH as u32,
         canvas_height: HEIGHT as u32,
         fov: 90.0,
-        depth: 10,
+        depth: 64,
         debug_number: 128,
         debug_information: DebugInformation::None,
         frame: 0,
         frames_without_move: 0.0,
+        min_depth: 3,
+        diffuse_depth: 8,
+        specular_depth: 16,
+        transmission_depth: 32,
+        color_mode: ColorMode::Rgb,
+        volume_depth: 32,
+        subsurface_depth: 1024,
+        aperture_radius: 0.0,
+        focus_distance: 20.0,
+        aperture_blades: 0,
+        projection: Projection::Perspective,
+        ortho_width: 20.0,
+        stereo: StereoLayout::Mono,
+        interocular_distance: 0.3,
+        convergence_distance: f32::INFINITY,
+        exposure_mode: ExposureMode::Manual,
+        //f/1, 1.2 seconds at iso 100 makes up for the 1 / 1.2 of the lens and sensor, leaving
+        //the radiance as it is
+        ev100: ev100(1.0, 1.2, 100.0),
+        exposure_compensation: 0.0,
+        tone_mapping: ToneMapping::Aces,
+        output_encoding: OutputEncoding::Srgb,
     };
 
     let transform_matrix = glam::Affine3A::from_scale_rotation_translation(
@@ -58,11 +83,20 @@ pub fn main() {
 
     let (scene_info, buffers) = 
         SceneBuilder::new()
-            // .add_obj_file(include_str!("./resources/dragon_8k.obj"), &[transform_matrix_dragon])
-            .add_obj_file(include_str!("./resources/default_cube.obj"), &[transform_matrix_default_cube])
-            .add_obj_file(include_str!("./resources/cornel_box.obj"), &[transform_matrix_3])
-            .add_obj_file(include_str!("./resources/teapot.obj"), &[transform_matrix])
+            //glass
+            .add_material(PrincipledMaterial::new().base_color(Vec3::ONE).roughness(0.0).transmission(1.0, 1.5))
+            .add_material(PrincipledMaterial::new().model(MaterialModel::Normals))
+            //light
+            .add_material(PrincipledMaterial::new().base_color(Vec3::ZERO).emission(Vec3::ONE, 15.0))
+            // .add_obj_file(include_str!("./resources/dragon_8k.obj"), &[transform_matrix_dragon], 0)
+            .add_obj_file(include_str!("./resources/default_cube.obj"), &[transform_matrix_default_cube], 0)
+            .add_obj_file(include_str!("./resources/cornel_box.obj"), &[transform_matrix_3], 1)
+            .add_obj_file(include_str!("./resources/teapot.obj"), &[transform_matrix], 2)
             .sun_orientation(Vec3::new(1.0, -1.0, 1.0))
+            // .environment_map("./program/src/resources/environment.hdr", 0.0, 1.0)
+            // .add_texture(include_bytes!("./resources/earth_1.jpg"))
+            // .camera_path("./program/src/resources/camera_path.txt")
+            // .sequence(24.0, 64)
             .build();
 
     println!(
@@ -77,6 +111,8 @@ pub fn main() {
 
     let mut winit_app = WinitApp {
         locked: false,
+        cursor: (0.0, 0.0),
+        sequence_frame: None,
         frame_count: 0,
         start_time: std::time::Instant::now(),
         app: None,
@@ -88,8 +124,20 @@ pub fn main() {
     let _res = event_loop.run_app(&mut winit_app);
 }
 
+/// Puts the camera where the camera path has it at `time` and starts accumulating anew.
+fn follow_camera_path(app: &mut modules::vulkan::App, time: f32) {
+    let (transform, fov) = app.camera_path().sample(time);
+    app.cam_data.transform = transform;
+    app.cam_data.fov = fov;
+    app.cam_data.frames_without_move = 0.0;
+}
+
 struct WinitApp {
     locked: bool,
+    /// in physical pixels of the window
+    cursor: (f32, f32),
+    /// frame of the camera path being rendered into an image, if the sequence is rendering
+    sequence_frame: Option<u32>,
     frame_count: usize,
     start_time: std::time::Instant,
     app: Option<(modules::vulkan::App, winit::window::Window)>,
@@ -128,6 +176,23 @@ impl ApplicationHandler for WinitApp {
     ) {
         if let WindowEvent::RedrawRequested = event {
             if let Some((app, window)) = &mut self.app {
+                if let Some(frame) = self.sequence_frame {
+                    if app.cam_data.frames_without_move >= app.camera_path().samples as f32 {
+                        let path = format!("{}/frame_{:04}.png", SEQUENCE_DIRECTORY, frame);
+                        unsafe { app.save_output(&path).unwrap() };
+                        println!("saved {}", path);
+
+                        let time = app.camera_path().start() + (frame + 1) as f32 / app.camera_path().fps;
+                        if time > app.camera_path().end() {
+                            println!("sequence done");
+                            self.sequence_frame = None;
+                        } else {
+                            follow_camera_path(app, time);
+                            self.sequence_frame = Some(frame + 1);
+                        }
+                    }
+                }
+
                 static mut PREV_CAMERA_TRANSFORM: glam::Affine3A = glam::Affine3A::IDENTITY;
                 let current_camera_transform = app.cam_data.transform;
                 if current_camera_transform != unsafe { PREV_CAMERA_TRANSFORM } {
@@ -156,6 +221,8 @@ impl ApplicationHandler for WinitApp {
                 }
                 event_loop.exit()
             }
+        } else if let WindowEvent::CursorMoved { position, .. } = event {
+            self.cursor = (position.x as f32, position.y as f32);
         } else if let WindowEvent::KeyboardInput { event, .. } = event {
             if let Some((app, window)) = &mut self.app {
                 match event.physical_key {
@@ -234,6 +301,131 @@ impl ApplicationHandler for WinitApp {
                             app.cam_data.debug_information
                         );
                     }
+                    PhysicalKey::Code(KeyCode::KeyC) => {
+                        app.cam_data.frames_without_move = 0.0;
+                        if event.state == winit::event::ElementState::Released {
+                            return;
+                        }
+                        app.cam_data.color_mode = match app.cam_data.color_mode {
+                            ColorMode::Rgb => ColorMode::Spectral,
+                            ColorMode::Spectral => ColorMode::Rgb,
+                        };
+                        println!("color mode: {:?}", app.cam_data.color_mode);
+                    }
+                    PhysicalKey::Code(KeyCode::KeyP) => {
+                        app.cam_data.frames_without_move = 0.0;
+                        if event.state == winit::event::ElementState::Released {
+                            return;
+                        }
+                        app.cam_data.projection = match app.cam_data.projection {
+                            Projection::Perspective => Projection::Orthographic,
+                            Projection::Orthographic => Projection::Fisheye,
+                            Projection::Fisheye => Projection::Equirectangular,
+                            Projection::Equirectangular => Projection::Perspective,
+                        };
+                        println!("projection: {:?}", app.cam_data.projection);
+                    }
+                    PhysicalKey::Code(KeyCode::KeyV) => {
+                        app.cam_data.frames_without_move = 0.0;
+                        if event.state == winit::event::ElementState::Released {
+                            return;
+                        }
+                        let stereo = match app.cam_data.stereo {
+                            StereoLayout::Mono => StereoLayout::SideBySide,
+                            StereoLayout::SideBySide => StereoLayout::OverUnder,
+                            StereoLayout::OverUnder => StereoLayout::Mono,
+                        };
+                        unsafe { app.set_stereo(window, stereo).unwrap() };
+                        println!("stereo: {:?}", app.cam_data.stereo);
+                    }
+                    PhysicalKey::Code(KeyCode::KeyX) => {
+                        if event.state == winit::event::ElementState::Released {
+                            return;
+                        }
+                        app.cam_data.exposure_mode = match app.cam_data.exposure_mode {
+                            ExposureMode::Manual => ExposureMode::Auto,
+                            ExposureMode::Auto => ExposureMode::Manual,
+                        };
+                        println!("exposure: {:?}", app.cam_data.exposure_mode);
+                    }
+                    PhysicalKey::Code(KeyCode::Equal) => {
+                        if event.state == winit::event::ElementState::Released {
+                            return;
+                        }
+                        app.cam_data.exposure_compensation += 0.5;
+                        println!("exposure compensation: {}", app.cam_data.exposure_compensation);
+                    }
+                    PhysicalKey::Code(KeyCode::Minus) => {
+                        if event.state == winit::event::ElementState::Released {
+                            return;
+                        }
+                        app.cam_data.exposure_compensation -= 0.5;
+                        println!("exposure compensation: {}", app.cam_data.exposure_compensation);
+                    }
+                    PhysicalKey::Code(KeyCode::KeyT) => {
+                        //only changes how the accumulated image is displayed, so it keeps going
+                        if event.state == winit::event::ElementState::Released {
+                            return;
+                        }
+                        app.cam_data.tone_mapping = match app.cam_data.tone_mapping {
+                            ToneMapping::None => ToneMapping::Reinhard,
+                            ToneMapping::Reinhard => ToneMapping::Filmic,
+                            ToneMapping::Filmic => ToneMapping::Aces,
+                            ToneMapping::Aces => ToneMapping::Agx,
+                            ToneMapping::Agx => ToneMapping::None,
+                        };
+                        println!("tone mapping: {:?}", app.cam_data.tone_mapping);
+                    }
+                    PhysicalKey::Code(KeyCode::KeyM) => {
+                        if event.state == winit::event::ElementState::Released {
+                            return;
+                        }
+                        if app.camera_path().is_empty() {
+                            println!("no camera path to render");
+                            return;
+                        }
+                        std::fs::create_dir_all(SEQUENCE_DIRECTORY).unwrap();
+                        let start = app.camera_path().start();
+                        follow_camera_path(app, start);
+                        self.sequence_frame = Some(0);
+                        println!("rendering the camera path into {}", SEQUENCE_DIRECTORY);
+                    }
+                    PhysicalKey::Code(KeyCode::KeyF) => {
+                        if event.state == winit::event::ElementState::Released {
+                            return;
+                        }
+                        //a locked cursor stays in the middle of the window
+                        let size = window.inner_size();
+                        let pixel = if self.locked {
+                            (size.width as f32 / 2.0, size.height as f32 / 2.0)
+                        } else {
+                            self.cursor
+                        };
+                        //stereo canvases are squeezed into the window along one side
+                        let scale_x = app.cam_data.canvas_width as f32 / size.width as f32;
+                        let scale_y = app.cam_data.canvas_height as f32 / size.height as f32;
+                        app.focus_at((pixel.0 * scale_x, pixel.1 * scale_y));
+                    }
+                    PhysicalKey::Code(KeyCode::BracketRight) => {
+                        app.cam_data.frames_without_move = 0.0;
+                        if event.state == winit::event::ElementState::Released {
+                            return;
+                        }
+                        app.cam_data.aperture_radius = (app.cam_data.aperture_radius * 2.0).max(0.01);
+                        println!("aperture radius: {}", app.cam_data.aperture_radius);
+                    }
+                    PhysicalKey::Code(KeyCode::BracketLeft) => {
+                        app.cam_data.frames_without_move = 0.0;
+                        if event.state == winit::event::ElementState::Released {
+                            return;
+                        }
+                        //small enough apertures snap back to a pinhole
+                        app.cam_data.aperture_radius /= 2.0;
+                        if app.cam_data.aperture_radius < 0.01 {
+                            app.cam_data.aperture_radius = 0.0;
+                        }
+                        println!("aperture radius: {}", app.cam_data.aperture_radius);
+                    }
                     PhysicalKey::Code(KeyCode::NumpadAdd) => {
                         app.cam_data.frames_without_move = 0.0;
                         if event.state == winit::event::ElementState::Released {
@@ -0,0 +1,155 @@
+use std::fs;
+use std::io;
+
+use shared::glam::{Affine3A, EulerRot, Quat, Vec3};
+
+/// Where the camera is at `time` seconds into the path.
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe {
+    pub time: f32,
+    pub position: Vec3,
+    pub rotation: Quat,
+    /// in degrees
+    pub fov: f32,
+}
+
+/// Keyframed camera flythrough, positions follow a spline through the keyframes while the
+/// rotations are slerped between them.
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+    /// frames a second of the rendered sequence
+    pub fps: f32,
+    /// samples accumulated for every frame of the rendered sequence
+    pub samples: u32,
+}
+
+impl Default for CameraPath {
+    fn default() -> Self {
+        CameraPath {
+            keyframes: Vec::new(),
+            fps: 24.0,
+            samples: 64,
+        }
+    }
+}
+
+impl CameraPath {
+    /// Loads keyframes from a text file with one per line, as
+    /// `time x y z yaw pitch roll fov`, the angles being in degrees with yaw and pitch turning
+    /// the camera the same way the mouse does. Empty lines and lines starting with `#` are
+    /// skipped, the keyframes may come in any order.
+    pub fn load(path: &str) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut keyframes = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let values = line
+                .split_whitespace()
+                .map(|value| value.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .ok()
+                .filter(|values| values.len() == 8)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{}:{} needs 8 numbers: time x y z yaw pitch roll fov", path, number + 1),
+                    )
+                })?;
+            keyframes.push(Keyframe {
+                time: values[0],
+                position: Vec3::new(values[1], values[2], values[3]),
+                rotation: Quat::from_euler(
+                    EulerRot::YXZ,
+                    values[4].to_radians(),
+                    values[5].to_radians(),
+                    values[6].to_radians(),
+                ),
+                fov: values[7],
+            });
+        }
+        println!("Loaded {} camera keyframes from {}", keyframes.len(), path);
+
+        let mut camera_path = CameraPath::default();
+        for keyframe in keyframes {
+            camera_path.add(keyframe);
+        }
+        Ok(camera_path)
+    }
+
+    pub fn add(&mut self, keyframe: Keyframe) {
+        let index = self.keyframes.partition_point(|k| k.time <= keyframe.time);
+        self.keyframes.insert(index, keyframe);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keyframes.is_empty()
+    }
+
+    /// Time of the last keyframe, the path starts at the first one.
+    pub fn end(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |k| k.time)
+    }
+
+    pub fn start(&self) -> f32 {
+        self.keyframes.first().map_or(0.0, |k| k.time)
+    }
+
+    /// Camera transform and fov at `time`, held still before the first and after the last
+    /// keyframe.
+    pub fn sample(&self, time: f32) -> (Affine3A, f32) {
+        let keys = &self.keyframes;
+        assert!(!keys.is_empty(), "sampled a camera path without keyframes");
+
+        let next = keys.partition_point(|k| k.time <= time);
+        if next == 0 {
+            return Self::transform(&keys[0]);
+        }
+        if next == keys.len() {
+            return Self::transform(&keys[keys.len() - 1]);
+        }
+
+        let i = next - 1;
+        let (a, b) = (&keys[i], &keys[next]);
+        let duration = b.time - a.time;
+        let t = (time - a.time) / duration;
+
+        //cubic hermite with catmull-rom tangents, which stay right for unevenly spaced keyframes
+        let t2 = t * t;
+        let t3 = t2 * t;
+        let position = a.position * (2.0 * t3 - 3.0 * t2 + 1.0)
+            + self.tangent(i) * (duration * (t3 - 2.0 * t2 + t))
+            + b.position * (-2.0 * t3 + 3.0 * t2)
+            + self.tangent(next) * (duration * (t3 - t2));
+
+        let keyframe = Keyframe {
+            time,
+            position,
+            rotation: a.rotation.slerp(b.rotation, t),
+            fov: a.fov + (b.fov - a.fov) * t,
+        };
+        Self::transform(&keyframe)
+    }
+
+    //velocity through keyframe `i`, one sided at the ends of the path
+    fn tangent(&self, i: usize) -> Vec3 {
+        let keys = &self.keyframes;
+        let before = &keys[i.saturating_sub(1)];
+        let after = &keys[(i + 1).min(keys.len() - 1)];
+        let duration = after.time - before.time;
+        if duration <= 0.0 {
+            return Vec3::ZERO;
+        }
+        (after.position - before.position) / duration
+    }
+
+    fn transform(keyframe: &Keyframe) -> (Affine3A, f32) {
+        (
+            Affine3A::from_scale_rotation_translation(Vec3::ONE, keyframe.rotation, keyframe.position),
+            keyframe.fov,
+        )
+    }
+}
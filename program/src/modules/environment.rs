@@ -0,0 +1,81 @@
+use std::f32::consts::PI;
+
+use shared::glam::Vec4;
+
+/// Equirectangular HDR environment together with the tables used to importance sample it.
+pub struct EnvironmentMap {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Vec4>,
+    /// `height + 1` entries of the marginal CDF over rows, followed by `width + 1` entries of
+    /// the conditional CDF for every row
+    pub cdf: Vec<f32>,
+}
+
+impl EnvironmentMap {
+    /// Loads a Radiance `.hdr` or OpenEXR file with the usual equirectangular layout,
+    /// the top row looking straight up.
+    pub fn load(path: &str) -> image::ImageResult<Self> {
+        let image = image::open(path)?.into_rgba32f();
+        let (width, height) = image.dimensions();
+        let pixels = image
+            .pixels()
+            .map(|p| Vec4::new(p[0], p[1], p[2], 1.0))
+            .collect::<Vec<_>>();
+
+        println!("Loaded {}x{} environment map from {}", width, height, path);
+
+        Ok(Self::from_pixels(width, height, pixels))
+    }
+
+    pub fn from_pixels(width: u32, height: u32, pixels: Vec<Vec4>) -> Self {
+        let cdf = build_cdf(width as usize, height as usize, &pixels);
+        EnvironmentMap {
+            width,
+            height,
+            pixels,
+            cdf,
+        }
+    }
+}
+
+impl Default for EnvironmentMap {
+    //the shader always needs something bound, even with no environment loaded
+    fn default() -> Self {
+        Self::from_pixels(1, 1, vec![Vec4::new(0.0, 0.0, 0.0, 1.0)])
+    }
+}
+
+fn build_cdf(width: usize, height: usize, pixels: &[Vec4]) -> Vec<f32> {
+    let mut marginal = vec![0.0; height + 1];
+    let mut conditional = vec![0.0; height * (width + 1)];
+
+    for y in 0..height {
+        //rows near the poles cover less of the sphere
+        let sin_theta = (PI * (y as f32 + 0.5) / height as f32).sin();
+        let row = &mut conditional[y * (width + 1)..(y + 1) * (width + 1)];
+
+        for x in 0..width {
+            let p = pixels[y * width + x];
+            let luminance = 0.2126 * p.x + 0.7152 * p.y + 0.0722 * p.z;
+            row[x + 1] = row[x] + luminance.max(0.0) * sin_theta;
+        }
+
+        let row_sum = row[width];
+        marginal[y + 1] = marginal[y] + row_sum;
+        normalize_cdf(row);
+    }
+    normalize_cdf(&mut marginal);
+
+    marginal.append(&mut conditional);
+    marginal
+}
+
+fn normalize_cdf(cdf: &mut [f32]) {
+    let total = cdf[cdf.len() - 1];
+    let len = (cdf.len() - 1) as f32;
+    for (i, value) in cdf.iter_mut().enumerate() {
+        //completely black rows and maps fall back to uniform sampling
+        *value = if total > 0.0 { *value / total } else { i as f32 / len };
+    }
+}
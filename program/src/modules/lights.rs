@@ -0,0 +1,198 @@
+use std::f32::consts::PI;
+use std::ops::Range;
+
+use shared::glam::{Quat, Vec3};
+use shared::{
+    BoundingBox, ChildTriangleMode, Instance, LightNode, LightTriangle, PrincipledMaterial, Vertex,
+};
+
+/// Every triangle of an emissive instance together with the tree the shader picks them with,
+/// by how much light they could deliver to the point being shaded.
+/// Based on: Conty Estevez and Kulla, "Importance Sampling of Many Lights with Adaptive Tree Splitting"
+pub struct LightTree {
+    pub lights: Vec<LightTriangle>,
+    pub nodes: Vec<LightNode>,
+}
+
+/// Where some light triangles are, which way they face and how bright they are.
+#[derive(Clone, Copy)]
+struct LightBounds {
+    min: Vec3,
+    max: Vec3,
+    axis: Vec3,
+    cos_theta_o: f32,
+    cos_theta_e: f32,
+    power: f32,
+}
+
+impl LightBounds {
+    fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    fn union(&self, other: &LightBounds) -> LightBounds {
+        let (axis, cos_theta_o) =
+            cone_union(self.axis, self.cos_theta_o, other.axis, other.cos_theta_o);
+        LightBounds {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+            axis,
+            cos_theta_o,
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+            power: self.power + other.power,
+        }
+    }
+}
+
+/// Smallest cone holding two others, given by their axes and the cosines of their half angles.
+fn cone_union(axis_a: Vec3, cos_a: f32, axis_b: Vec3, cos_b: f32) -> (Vec3, f32) {
+    let theta_a = cos_a.clamp(-1.0, 1.0).acos();
+    let theta_b = cos_b.clamp(-1.0, 1.0).acos();
+    let theta_d = axis_a.angle_between(axis_b);
+
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return (axis_a, cos_a);
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return (axis_b, cos_b);
+    }
+
+    let theta_o = (theta_a + theta_d + theta_b) / 2.0;
+    let rotation_axis = axis_a.cross(axis_b);
+    if theta_o >= PI || rotation_axis.length_squared() == 0.0 {
+        return (axis_a, -1.0);
+    }
+    //turn the first axis towards the second one until the cone covers both
+    let axis = Quat::from_axis_angle(rotation_axis.normalize(), theta_o - theta_a) * axis_a;
+    (axis, theta_o.cos())
+}
+
+pub fn build_light_tree(
+    vertices: &[Vertex],
+    triangles: &[(u32, u32, u32)],
+    object_triangles: &[Range<u32>],
+    instances: &[Instance],
+    materials: &[PrincipledMaterial],
+) -> LightTree {
+    let mut lights = Vec::new();
+    let mut bounds = Vec::new();
+
+    //going through instances and triangles in order keeps the light buffer sorted
+    for (instance_id, instance) in instances.iter().enumerate() {
+        let luminance = materials[instance.material_id as usize].emitted_luminance();
+        if luminance <= 0.0 {
+            continue;
+        }
+
+        for triangle_id in object_triangles[instance.object_id as usize].clone() {
+            let (a, b, c) = triangles[triangle_id as usize];
+            let a = instance.transform.transform_point3(vertices[a as usize].pos);
+            let b = instance.transform.transform_point3(vertices[b as usize].pos);
+            let c = instance.transform.transform_point3(vertices[c as usize].pos);
+            let cross = (b - a).cross(c - a);
+            let power = cross.length() * 0.5 * luminance;
+            if power <= 0.0 {
+                continue;
+            }
+
+            lights.push(LightTriangle {
+                instance_id: instance_id as u32,
+                triangle_id,
+                tree_path: 0,
+            });
+            bounds.push(LightBounds {
+                min: a.min(b).min(c),
+                max: a.max(b).max(c),
+                axis: cross.normalize(),
+                cos_theta_o: 1.0,
+                //lambertian emitters give off light all the way to the horizon
+                cos_theta_e: 0.0,
+                power,
+            });
+        }
+    }
+
+    let mut nodes = Vec::new();
+    if !lights.is_empty() {
+        let mut order = (0..lights.len() as u32).collect::<Vec<_>>();
+        build_node(&bounds, &mut order, &mut lights, &mut nodes, 0, 0);
+    }
+
+    println!(
+        "Light tree: {} emissive triangles, {} nodes",
+        lights.len(),
+        nodes.len()
+    );
+
+    LightTree { lights, nodes }
+}
+
+//splits the lights in half along the axis their centroids spread the most on, which keeps
+//the tree shallow enough for the paths to fit in a u32
+fn build_node(
+    bounds: &[LightBounds],
+    order: &mut [u32],
+    lights: &mut [LightTriangle],
+    nodes: &mut Vec<LightNode>,
+    path: u32,
+    depth: u32,
+) -> u32 {
+    let total = order[1..]
+        .iter()
+        .fold(bounds[order[0] as usize], |total, &i| total.union(&bounds[i as usize]));
+
+    let index = nodes.len() as u32;
+    nodes.push(LightNode {
+        bounding_box: BoundingBox {
+            min: total.min,
+            padding_1: [0; 4],
+            max: total.max,
+            padding_2: [0; 4],
+        },
+        axis: total.axis,
+        padding: [0; 4],
+        cos_theta_o: total.cos_theta_o,
+        cos_theta_e: total.cos_theta_e,
+        power: total.power,
+        child_1_or_light: order[0],
+        child_2: 0,
+        mode: ChildTriangleMode::Triangles,
+    });
+
+    if order.len() == 1 {
+        lights[order[0] as usize].tree_path = path;
+        return index;
+    }
+    assert!(depth < 32, "light tree too deep");
+
+    let (centroid_min, centroid_max) = order.iter().fold(
+        (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+        |(min, max), &i| {
+            let centroid = bounds[i as usize].centroid();
+            (min.min(centroid), max.max(centroid))
+        },
+    );
+    let extent = centroid_max - centroid_min;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+
+    let middle = order.len() / 2;
+    order.select_nth_unstable_by(middle, |&a, &b| {
+        bounds[a as usize].centroid()[axis].total_cmp(&bounds[b as usize].centroid()[axis])
+    });
+    let (first, second) = order.split_at_mut(middle);
+
+    let child_1 = build_node(bounds, first, lights, nodes, path, depth + 1);
+    let child_2 = build_node(bounds, second, lights, nodes, path | (1 << depth), depth + 1);
+
+    let node = &mut nodes[index as usize];
+    node.child_1_or_light = child_1;
+    node.child_2 = child_2;
+    node.mode = ChildTriangleMode::Children;
+    index
+}
@@ -1,10 +1,30 @@
 pub mod vulkan;
+pub mod animation;
 pub mod bvh;
-use glam::Vec3;
+pub mod environment;
+pub mod lights;
+pub mod pick;
+pub mod tangent;
+pub mod texture;
+pub mod volume;
+use std::collections::HashMap;
+use std::ops::Range;
+
+use animation::CameraPath;
+use environment::EnvironmentMap;
+use glam::{UVec3, Vec2, Vec3, Vec4};
+use image::RgbaImage;
 use shared::{glam::Affine3A, *};
+use texture::TextureArray;
+use volume::DensityGrids;
 
 pub fn parse_obj_file(file: &str) -> (Vec<Vertex>, Vec<(u32, u32, u32)>) {
+    let mut positions: Vec<Vec3> = Vec::new();
+    let mut uvs: Vec<Vec2> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
     let mut vertices: Vec<Vertex> = Vec::new();
+    //every distinct position/uv/normal triple becomes its own vertex
+    let mut vertex_ids: HashMap<(usize, Option<usize>, Option<usize>), u32> = HashMap::new();
     let mut faces: Vec<(u32, u32, u32)> = Vec::new();
 
     for line in file.lines() {
@@ -14,34 +34,86 @@ pub fn parse_obj_file(file: &str) -> (Vec<Vertex>, Vec<(u32, u32, u32)>) {
                 let x = line.next().unwrap().parse::<f32>().unwrap();
                 let y = line.next().unwrap().parse::<f32>().unwrap();
                 let z = line.next().unwrap().parse::<f32>().unwrap();
-                vertices.push(Vertex::new(Vec3::new(x, y, z)));
+                positions.push(Vec3::new(x, y, z));
+            }
+            Some("vt") => {
+                let u = line.next().unwrap().parse::<f32>().unwrap();
+                let v = line.next().unwrap().parse::<f32>().unwrap();
+                //obj puts v = 0 at the bottom of the image, textures start at the top
+                uvs.push(Vec2::new(u, 1.0 - v));
+            }
+            Some("vn") => {
+                let x = line.next().unwrap().parse::<f32>().unwrap();
+                let y = line.next().unwrap().parse::<f32>().unwrap();
+                let z = line.next().unwrap().parse::<f32>().unwrap();
+                normals.push(Vec3::new(x, y, z).normalize_or_zero());
             }
             Some("f") => {
-                let vertices: Vec<&str> = line.collect();
-                let v1 = vertices[0].split('/').next().unwrap().parse::<i32>().unwrap() - 1;
-                let mut prev = vertices[1].split('/').next().unwrap().parse::<i32>().unwrap() - 1;
-                for v in vertices.iter().skip(2) {
-                    let v2 = v.split('/').next().unwrap().parse::<i32>().unwrap() - 1;
-                    let v1_u32 = if v1 < 0 { (vertices.len() as i32 + v1) as u32 } else { v1 as u32 };
-                    let prev_u32 = if prev < 0 { (vertices.len() as i32 + prev) as u32 } else { prev as u32 };
-                    let v2_u32 = if v2 < 0 { (vertices.len() as i32 + v2) as u32 } else { v2 as u32 };
-                    faces.push((v1_u32, prev_u32, v2_u32));
-                    prev = v2;
+                let corners: Vec<u32> = line
+                    .map(|corner| {
+                        let mut indices = corner.split('/');
+                        let pos = resolve_obj_index(indices.next().unwrap(), positions.len());
+                        let uv = indices
+                            .next()
+                            .filter(|i| !i.is_empty())
+                            .map(|i| resolve_obj_index(i, uvs.len()));
+                        let normal = indices
+                            .next()
+                            .filter(|i| !i.is_empty())
+                            .map(|i| resolve_obj_index(i, normals.len()));
+                        *vertex_ids.entry((pos, uv, normal)).or_insert_with(|| {
+                            let uv = uv.map_or(Vec2::ZERO, |uv| uvs[uv]);
+                            let normal = normal.map_or(Vec3::ZERO, |normal| normals[normal]);
+                            vertices.push(Vertex::new(positions[pos], normal, Vec4::ZERO, uv));
+                            vertices.len() as u32 - 1
+                        })
+                    })
+                    .collect();
+                for i in 1..corners.len() - 1 {
+                    faces.push((corners[0], corners[i], corners[i + 1]));
                 }
             }
             _ => {}
         }
     }
+    tangent::generate_tangents(&mut vertices, &faces);
     (vertices, faces)
 }
 
+//obj indices start at 1, negative ones count back from the last element read so far
+fn resolve_obj_index(index: &str, count: usize) -> usize {
+    let index = index.parse::<i64>().unwrap();
+    if index < 0 {
+        (count as i64 + index) as usize
+    } else {
+        (index - 1) as usize
+    }
+}
+
 pub struct SceneBuilder {
     vertices: Vec<Vertex>,
     tris: Vec<(u32, u32, u32)>,
     bvh: Vec<Bvh>,
     instance: Vec<Instance>,
     objects: Vec<Object>,
+    //the triangles of every object, the lights are found through these
+    object_triangles: Vec<Range<u32>>,
+    materials: Vec<PrincipledMaterial>,
     sun_orientation: Vec3,
+    sun_color: Vec3,
+    sun_intensity: f32,
+    sun_angular_diameter: f32,
+    sky_turbidity: f32,
+    sky_intensity: f32,
+    ground_albedo: Vec3,
+    environment: Option<EnvironmentMap>,
+    environment_rotation: f32,
+    environment_intensity: f32,
+    textures: Vec<RgbaImage>,
+    density_grids: DensityGrids,
+    camera_path: CameraPath,
+    sequence_fps: f32,
+    sequence_samples: u32,
 }
 
 impl SceneBuilder {
@@ -53,11 +125,28 @@ impl SceneBuilder {
             bvh: Vec::new(),
             instance: Vec::new(),
             objects: Vec::new(),
+            object_triangles: Vec::new(),
+            materials: Vec::new(),
             sun_orientation: Vec3::new(1.0, -1.0, 1.0),
+            sun_color: Vec3::new(1.0, 0.95, 0.85),
+            sun_intensity: 3.0,
+            sun_angular_diameter: 0.53,
+            sky_turbidity: 2.0,
+            sky_intensity: 1.0,
+            ground_albedo: Vec3::new(0.3, 0.3, 0.3),
+            environment: None,
+            environment_rotation: 0.0,
+            environment_intensity: 1.0,
+            textures: Vec::new(),
+            density_grids: DensityGrids::default(),
+            camera_path: CameraPath::default(),
+            sequence_fps: 24.0,
+            sequence_samples: 64,
         }
     }
 
-    pub fn add_obj_file(mut self, file: &str, instance_matrices: &[Affine3A]) -> Self {
+    /// Adds a mesh with one instance per matrix, all shaded with material `material_id`.
+    pub fn add_obj_file(mut self, file: &str, instance_matrices: &[Affine3A], material_id: u32) -> Self {
         let (mut vertices, mut tris) = parse_obj_file(file);
         println!(
             "Adding {} vertices and {} triangles from OBJ file",
@@ -96,37 +185,179 @@ impl SceneBuilder {
         self.objects.push(Object {
             bvh_root: bvh_offset,
         });
+        self.object_triangles.push(tri_offset..self.tris.len() as u32);
         self.instance.extend(
             instance_matrices
                 .iter()
                 .map(|m| Instance {
                     transform: *m,
                     object_id: object_offset,
+                    material_id,
                 })
         );
 
         self
     }
 
+    /// Materials are referred to by the order in which they were added, starting at 0.
+    pub fn add_material(mut self, material: PrincipledMaterial) -> Self {
+        self.materials.push(material);
+        self
+    }
+
+    /// Decodes an image file and appends it to the texture array.
+    /// Materials refer to textures by the order in which they were added, starting at 0.
+    pub fn add_texture(mut self, file: &[u8]) -> Self {
+        let texture = image::load_from_memory(file).unwrap().into_rgba8();
+        println!(
+            "Adding {}x{} texture {}",
+            texture.width(),
+            texture.height(),
+            self.textures.len()
+        );
+        self.textures.push(texture);
+        self
+    }
+
+    /// Loads a raw grid of `f32` densities, see [`DensityGrids::load_raw`].
+    /// Materials refer to grids by the order in which they were added, starting at 0.
+    pub fn add_density_grid(mut self, path: &str, resolution: UVec3) -> Self {
+        self.density_grids.load_raw(path, resolution).unwrap();
+        self
+    }
+
     pub fn sun_orientation(mut self, orientation: Vec3) -> Self {
         self.sun_orientation = orientation;
         self
     }
 
-    pub fn build(self) -> (SceneInfo, BufferSceneInfo) {
+    pub fn sun_color(mut self, color: Vec3) -> Self {
+        self.sun_color = color;
+        self
+    }
+
+    pub fn sun_intensity(mut self, intensity: f32) -> Self {
+        self.sun_intensity = intensity;
+        self
+    }
+
+    /// angular diameter of the sun disk in degrees, the real sun is about 0.53
+    pub fn sun_angular_diameter(mut self, degrees: f32) -> Self {
+        self.sun_angular_diameter = degrees;
+        self
+    }
+
+    /// haziness of the atmosphere, values between 2.0 (clear) and 10.0 (hazy) look reasonable
+    pub fn sky_turbidity(mut self, turbidity: f32) -> Self {
+        self.sky_turbidity = turbidity;
+        self
+    }
+
+    pub fn sky_intensity(mut self, intensity: f32) -> Self {
+        self.sky_intensity = intensity;
+        self
+    }
+
+    pub fn ground_albedo(mut self, albedo: Vec3) -> Self {
+        self.ground_albedo = albedo;
+        self
+    }
+
+    /// Lights the scene with an equirectangular `.hdr` or `.exr` image instead of the sky and
+    /// its sun. The rotation around the y axis is in degrees.
+    pub fn environment_map(mut self, path: &str, rotation: f32, intensity: f32) -> Self {
+        self.environment = Some(EnvironmentMap::load(path).unwrap());
+        self.environment_rotation = rotation;
+        self.environment_intensity = intensity;
+        self
+    }
+
+    /// Keyframes for rendering a flythrough, see [`CameraPath::load`] for the file format.
+    pub fn camera_path(mut self, path: &str) -> Self {
+        self.camera_path = CameraPath::load(path).unwrap();
+        self
+    }
+
+    /// How the camera path is rendered into a sequence, `fps` frames a second with `samples`
+    /// accumulated for each.
+    pub fn sequence(mut self, fps: f32, samples: u32) -> Self {
+        self.sequence_fps = fps;
+        self.sequence_samples = samples;
+        self
+    }
+
+    pub fn build(mut self) -> (SceneInfo, BufferSceneInfo) {
+        let textures = TextureArray::new(&self.textures);
+
+        for instance in &self.instance {
+            assert!(
+                (instance.material_id as usize) < self.materials.len().max(1),
+                "instance uses material {}, but only {} were added",
+                instance.material_id,
+                self.materials.len()
+            );
+        }
+        for material in &self.materials {
+            assert!(
+                material.density_grid == NO_DENSITY_GRID
+                    || (material.density_grid as usize) < self.density_grids.grids.len(),
+                "material uses density grid {}, but only {} were added",
+                material.density_grid,
+                self.density_grids.grids.len()
+            );
+        }
+        //the shader always needs something bound
+        if self.materials.is_empty() {
+            self.materials.push(PrincipledMaterial::default());
+        }
+        let light_tree = lights::build_light_tree(
+            &self.vertices,
+            &self.tris,
+            &self.object_triangles,
+            &self.instance,
+            &self.materials,
+        );
+
         let scene_info = SceneInfo {
             num_instances: self.instance.len() as u32,
             num_bvh_nodes: self.bvh.len() as u32,
             num_triangles: self.tris.len() as u32,
+            sun_intensity: self.sun_intensity,
             sun_orientation: self.sun_orientation,
+            padding_1: [0; 4],
+            sun_color: self.sun_color,
+            padding_2: [0; 4],
+            sun_angular_diameter: self.sun_angular_diameter,
+            sky_turbidity: self.sky_turbidity,
+            sky_intensity: self.sky_intensity,
+            padding_3: [0; 4],
+            ground_albedo: self.ground_albedo,
+            padding_4: [0; 4],
+            environment_width: self.environment.as_ref().map_or(0, |e| e.width),
+            environment_height: self.environment.as_ref().map_or(0, |e| e.height),
+            environment_rotation: self.environment_rotation,
+            environment_intensity: self.environment_intensity,
+            texture_width: textures.width,
+            texture_height: textures.height,
+            num_lights: light_tree.lights.len() as u32,
         };
 
+        self.camera_path.fps = self.sequence_fps;
+        self.camera_path.samples = self.sequence_samples;
+
         let buffer_scene_info = BufferSceneInfo {
             vertices: self.vertices,
             triangles: self.tris,
             bvh: self.bvh,
             instances: self.instance,
             objects: self.objects,
+            materials: self.materials,
+            lights: light_tree.lights,
+            light_tree: light_tree.nodes,
+            environment: self.environment.unwrap_or_default(),
+            textures,
+            density_grids: self.density_grids.finish(),
+            camera_path: self.camera_path,
         };
 
         (scene_info, buffer_scene_info)
@@ -139,4 +370,11 @@ pub struct BufferSceneInfo {
     pub bvh: Vec<Bvh>,
     pub instances: Vec<Instance>,
     pub objects: Vec<Object>,
+    pub materials: Vec<PrincipledMaterial>,
+    pub lights: Vec<LightTriangle>,
+    pub light_tree: Vec<LightNode>,
+    pub environment: EnvironmentMap,
+    pub textures: TextureArray,
+    pub density_grids: DensityGrids,
+    pub camera_path: CameraPath,
 }
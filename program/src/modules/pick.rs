@@ -0,0 +1,103 @@
+use shared::glam::Vec3;
+use shared::{BoundingBox, CamData, ChildTriangleMode, MaterialModel};
+
+use super::BufferSceneInfo;
+
+/// Distance to whatever surface is seen through `pixel` the same way the shader would see it
+/// through a pinhole, which is its depth for the flat projections. Volume bounds are looked
+/// through.
+pub fn depth_at(cam_data: &CamData, buffers: &BufferSceneInfo, pixel: (f32, f32)) -> Option<f32> {
+    let (origin, direction) = cam_data.view_ray(pixel);
+    if direction == Vec3::ZERO {
+        return None;
+    }
+    let pos = cam_data.transform.transform_point3(origin);
+    let direction = cam_data.transform.transform_vector3(direction);
+
+    let mut closest = f32::INFINITY;
+    for instance in &buffers.instances {
+        if buffers.materials[instance.material_id as usize].model == MaterialModel::Volume {
+            continue;
+        }
+        let inverse_matrix = instance.transform.inverse();
+        let local_pos = inverse_matrix.transform_point3(pos);
+        let local_direction = inverse_matrix.transform_vector3(direction);
+        let root = buffers.objects[instance.object_id as usize].bvh_root;
+        closest = closest.min(hit_bvh(buffers, root, local_pos, local_direction, closest));
+    }
+    closest.is_finite().then_some(closest)
+}
+
+fn hit_bvh(buffers: &BufferSceneInfo, root: u32, pos: Vec3, direction: Vec3, mut t_max: f32) -> f32 {
+    let mut closest = f32::INFINITY;
+    let mut stack = vec![root];
+    while let Some(index) = stack.pop() {
+        let node = &buffers.bvh[index as usize];
+        if hits_bounding(&node.bounding_box, pos, direction) > t_max {
+            continue;
+        }
+
+        if matches!(node.mode, ChildTriangleMode::Children) {
+            stack.push(node.child_1_or_first_tri);
+            stack.push(node.child_2_or_last_tri);
+            continue;
+        }
+
+        for i in node.child_1_or_first_tri..=node.child_2_or_last_tri {
+            let (a, b, c) = buffers.triangles[i as usize];
+            let t = hit_triangle(
+                buffers.vertices[a as usize].pos,
+                buffers.vertices[b as usize].pos,
+                buffers.vertices[c as usize].pos,
+                pos,
+                direction,
+            );
+            if t < t_max {
+                t_max = t;
+                closest = t;
+            }
+        }
+    }
+    closest
+}
+
+fn hits_bounding(bounding_box: &BoundingBox, pos: Vec3, direction: Vec3) -> f32 {
+    let t_1 = (bounding_box.min - pos) / direction;
+    let t_2 = (bounding_box.max - pos) / direction;
+    let t_near = t_1.min(t_2).max_element();
+    let t_far = t_1.max(t_2).min_element();
+    if t_near < t_far && t_far > 0.0 {
+        t_near
+    } else {
+        f32::INFINITY
+    }
+}
+
+//Möller-Trumbore, both sides count since the camera can be inside of things
+fn hit_triangle(p0: Vec3, p1: Vec3, p2: Vec3, pos: Vec3, direction: Vec3) -> f32 {
+    let edge_1 = p1 - p0;
+    let edge_2 = p2 - p0;
+    let p = direction.cross(edge_2);
+    let determinant = edge_1.dot(p);
+    if determinant.abs() < f32::EPSILON {
+        return f32::INFINITY;
+    }
+
+    let to_pos = pos - p0;
+    let u = to_pos.dot(p) / determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return f32::INFINITY;
+    }
+    let q = to_pos.cross(edge_1);
+    let v = direction.dot(q) / determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return f32::INFINITY;
+    }
+
+    let t = edge_2.dot(q) / determinant;
+    if t > f32::EPSILON {
+        t
+    } else {
+        f32::INFINITY
+    }
+}
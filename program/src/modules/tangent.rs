@@ -0,0 +1,65 @@
+use shared::glam::{Vec2, Vec3};
+use shared::Vertex;
+
+/// Fills in per-vertex tangents for normal mapping, following the MikkTSpace conventions:
+/// tangents follow +u, bitangents +v with v pointing up the image, contributions of the
+/// faces around a vertex are weighted by their corner angle and the tangent is orthogonalized
+/// against the vertex normal, with the bitangent's handedness stored in `tangent.w`.
+pub fn generate_tangents(vertices: &mut [Vertex], triangles: &[(u32, u32, u32)]) {
+    let mut tangents = vec![Vec3::ZERO; vertices.len()];
+    let mut bitangents = vec![Vec3::ZERO; vertices.len()];
+    let mut face_normals = vec![Vec3::ZERO; vertices.len()];
+
+    for &(a, b, c) in triangles {
+        let corners = [a as usize, b as usize, c as usize];
+        let pos = corners.map(|i| vertices[i].pos);
+        //uvs are stored with v pointing down the image, normal maps are baked with it pointing up
+        let uv = corners.map(|i| Vec2::new(vertices[i].uv.x, 1.0 - vertices[i].uv.y));
+
+        let edge_1 = pos[1] - pos[0];
+        let edge_2 = pos[2] - pos[0];
+        let delta_uv_1 = uv[1] - uv[0];
+        let delta_uv_2 = uv[2] - uv[0];
+
+        let determinant = delta_uv_1.x * delta_uv_2.y - delta_uv_2.x * delta_uv_1.y;
+        if determinant.abs() < f32::EPSILON {
+            //no usable uv mapping on this face
+            continue;
+        }
+
+        let tangent = ((edge_1 * delta_uv_2.y - edge_2 * delta_uv_1.y) / determinant).normalize_or_zero();
+        let bitangent = ((edge_2 * delta_uv_1.x - edge_1 * delta_uv_2.x) / determinant).normalize_or_zero();
+        let face_normal = edge_1.cross(edge_2).normalize_or_zero();
+
+        for (i, &corner) in corners.iter().enumerate() {
+            let to_next = pos[(i + 1) % 3] - pos[i];
+            let to_previous = pos[(i + 2) % 3] - pos[i];
+            let angle = to_next.angle_between(to_previous);
+            if angle.is_nan() {
+                continue;
+            }
+
+            tangents[corner] += tangent * angle;
+            bitangents[corner] += bitangent * angle;
+            face_normals[corner] += face_normal * angle;
+        }
+    }
+
+    for (i, vertex) in vertices.iter_mut().enumerate() {
+        let normal = if vertex.normal != Vec3::ZERO {
+            vertex.normal
+        } else {
+            face_normals[i].normalize_or_zero()
+        };
+
+        //Gram-Schmidt, a zero tangent tells the shader this vertex can't be normal mapped
+        let tangent = (tangents[i] - normal * normal.dot(tangents[i])).normalize_or_zero();
+        let handedness = if normal.cross(tangent).dot(bitangents[i]) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+
+        vertex.tangent = tangent.extend(handedness);
+    }
+}
@@ -0,0 +1,51 @@
+use image::imageops::FilterType;
+use image::RgbaImage;
+
+/// All textures of a scene as layers of one RGBA8 texture array.
+/// The layers of a texture array must share a size, so smaller textures are scaled up to fit.
+pub struct TextureArray {
+    pub width: u32,
+    pub height: u32,
+    pub layers: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl TextureArray {
+    pub fn new(textures: &[RgbaImage]) -> Self {
+        if textures.is_empty() {
+            return Self::default();
+        }
+
+        let width = textures.iter().map(|t| t.width()).max().unwrap();
+        let height = textures.iter().map(|t| t.height()).max().unwrap();
+
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize * textures.len());
+        for texture in textures {
+            if texture.dimensions() == (width, height) {
+                pixels.extend_from_slice(texture.as_raw());
+            } else {
+                let resized = image::imageops::resize(texture, width, height, FilterType::Triangle);
+                pixels.extend_from_slice(resized.as_raw());
+            }
+        }
+
+        TextureArray {
+            width,
+            height,
+            layers: textures.len() as u32,
+            pixels,
+        }
+    }
+}
+
+impl Default for TextureArray {
+    //a single white texel, the shader always needs something bound
+    fn default() -> Self {
+        TextureArray {
+            width: 1,
+            height: 1,
+            layers: 1,
+            pixels: vec![255; 4],
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::fs;
+use std::io;
+
+use shared::glam::UVec3;
+use shared::DensityGrid;
+
+/// Every density grid of the scene, their voxels one after another in a single buffer.
+#[derive(Default)]
+pub struct DensityGrids {
+    pub grids: Vec<DensityGrid>,
+    pub densities: Vec<f32>,
+}
+
+impl DensityGrids {
+    /// Loads a raw dense grid, nothing but little endian `f32` densities with x changing the
+    /// fastest, then y, then z. The file has to hold exactly `resolution` of them.
+    pub fn load_raw(&mut self, path: &str, resolution: UVec3) -> io::Result<()> {
+        let bytes = fs::read(path)?;
+        let count = (resolution.x * resolution.y * resolution.z) as usize;
+        if bytes.len() != count * 4 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} holds {} bytes, a {}x{}x{} grid needs {}",
+                    path,
+                    bytes.len(),
+                    resolution.x,
+                    resolution.y,
+                    resolution.z,
+                    count * 4
+                ),
+            ));
+        }
+
+        let densities = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]).max(0.0))
+            .collect::<Vec<_>>();
+        println!(
+            "Loaded {}x{}x{} density grid from {}",
+            resolution.x, resolution.y, resolution.z, path
+        );
+        self.add(resolution, densities);
+        Ok(())
+    }
+
+    pub fn add(&mut self, resolution: UVec3, mut densities: Vec<f32>) {
+        assert_eq!(densities.len(), (resolution.x * resolution.y * resolution.z) as usize);
+        self.grids.push(DensityGrid {
+            width: resolution.x,
+            height: resolution.y,
+            depth: resolution.z,
+            offset: self.densities.len() as u32,
+            max_density: densities.iter().fold(0.0, |max, &d| d.max(max)),
+        });
+        self.densities.append(&mut densities);
+    }
+
+    /// The shader always needs something bound, even with no grids loaded.
+    pub fn finish(mut self) -> Self {
+        if self.grids.is_empty() {
+            self.grids.push(DensityGrid::default());
+        }
+        if self.densities.is_empty() {
+            self.densities.push(0.0);
+        }
+        self
+    }
+}
@@ -29,13 +29,17 @@ use vulkanalia::window as vk_window;
 use vulkanalia::Version;
 use winit::window::Window;
 
-use shared::{CamData, Instance as ObjInstance, Object, SceneInfo, Vertex};
+use shared::{CamData, DensityGrid, OutputEncoding, StereoLayout, HISTOGRAM_BINS, Instance as ObjInstance, LightNode, LightTriangle, Object, PrincipledMaterial, SceneInfo, Vertex};
 use vulkanalia::vk::ExtDebugUtilsExtension;
 use vulkanalia::vk::KhrSurfaceExtension;
 use vulkanalia::vk::KhrSwapchainExtension;
 
 use shared::Bvh;
 
+use crate::modules::animation::CameraPath;
+use crate::modules::environment::EnvironmentMap;
+use crate::modules::pick;
+use crate::modules::texture::TextureArray;
 use crate::modules::BufferSceneInfo;
 use crate::{HEIGHT, WIDTH};
 
@@ -57,22 +61,49 @@ const SHADER: &[u8] = include_bytes!(env!("shader.spv"));
 
 const MAX_FRAMES_IN_FLIGHT: usize = 1;
 
+/// What the shader writes the displayed image into, has to match the `rgba16f` format it
+/// declares. Float, so the linear values srgb swapchains get don't band in the darks.
+const OUTPUT_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
 //UPDATE DESCRIPTORS HERE
 const NUM_UNIFORM_DESCRIPTORS: u32 = 2;
-const NUM_STORAGE_DESCRIPTORS: u32 = 6;
+const NUM_STORAGE_DESCRIPTORS: u32 = 13;
 const NUM_IMAGE_DESCRIPTORS: u32 = 1;
+const NUM_COMBINED_SAMPLER_DESCRIPTORS: u32 = 1;
+const NUM_SAMPLED_IMAGE_DESCRIPTORS: u32 = 1;
+//one sampler per shared::WrapMode, in the same order
+const NUM_SAMPLER_DESCRIPTORS: u32 = 3;
 
 const MAX_VERTICES: usize = 1000000;
 const MAX_TRIANGLES: usize = 1000000;
 const MAX_OBJECTS: usize = 100;
 const MAX_INSTANCES: usize = 1000;
+const MAX_MATERIALS: usize = 256;
+const MAX_LIGHT_TRIANGLES: usize = 100000;
+const MAX_DENSITY_GRIDS: usize = 16;
+const MAX_DENSITY_VOXELS: usize = 256 * 256 * 256;
 const MAX_BVH_NODES: usize = MAX_VERTICES; //this more than covers all possible vertices in a scene
+const MAX_ENVIRONMENT_WIDTH: usize = 4096;
+const MAX_ENVIRONMENT_HEIGHT: usize = 2048;
+const MAX_TEXTURE_SIZE: u32 = 4096;
+//the smallest maxImageArrayLayers vulkan guarantees
+const MAX_TEXTURE_LAYERS: u32 = 256;
 
 const VERTEX_BUFFER_LEN: usize = std::mem::size_of::<Vertex>() * MAX_VERTICES;
 const TRIANGLE_BUFFER_LEN: usize = std::mem::size_of::<(u32, u32, u32)>() * MAX_TRIANGLES;
 const OBJECT_BUFFER_LEN: usize = std::mem::size_of::<Object>() * MAX_OBJECTS;
 const INSTANCE_BUFFER_LEN: usize = std::mem::size_of::<ObjInstance>() * MAX_INSTANCES;
 const BVH_BUFFER_LEN: usize = std::mem::size_of::<Bvh>() * MAX_BVH_NODES;
+const MATERIAL_BUFFER_LEN: usize = std::mem::size_of::<PrincipledMaterial>() * MAX_MATERIALS;
+const LIGHT_BUFFER_LEN: usize = std::mem::size_of::<LightTriangle>() * MAX_LIGHT_TRIANGLES;
+//a binary tree with a leaf per light
+const LIGHT_TREE_BUFFER_LEN: usize = std::mem::size_of::<LightNode>() * (2 * MAX_LIGHT_TRIANGLES - 1);
+const DENSITY_GRID_BUFFER_LEN: usize = std::mem::size_of::<DensityGrid>() * MAX_DENSITY_GRIDS;
+const DENSITY_BUFFER_LEN: usize = std::mem::size_of::<f32>() * MAX_DENSITY_VOXELS;
+//one histogram being filled and one being metered from
+const HISTOGRAM_BUFFER_LEN: usize = std::mem::size_of::<u32>() * 2 * HISTOGRAM_BINS as usize;
+const ENVIRONMENT_CDF_BUFFER_LEN: usize = std::mem::size_of::<f32>()
+    * (MAX_ENVIRONMENT_HEIGHT + 1 + MAX_ENVIRONMENT_HEIGHT * (MAX_ENVIRONMENT_WIDTH + 1));
 
 /// Our Vulkan app.
 pub(crate) struct App {
@@ -120,10 +151,107 @@ impl App {
             Affine3A::from_scale_rotation_translation(scale, rotation, translation + pos);
     }
 
+    pub fn camera_path(&self) -> &CameraPath {
+        &self.buffers.camera_path
+    }
+
+    /// Switches to another stereo layout, the canvas grows to fit both eyes at the full resolution
+    /// and is only scaled down to the window on the way to the screen.
+    pub(crate) unsafe fn set_stereo(&mut self, window: &Window, stereo: StereoLayout) -> Result<()> {
+        self.cam_data.stereo = stereo;
+        self.data.canvas_extent = canvas_extent(stereo);
+        self.cam_data.canvas_width = self.data.canvas_extent.width;
+        self.cam_data.canvas_height = self.data.canvas_extent.height;
+        self.cam_data.frames_without_move = 0.0;
+        self.recreate_swapchain(window)
+    }
+
+    /// Moves the plane in focus onto whatever surface is seen through `pixel`, if there is one.
+    pub fn focus_at(&mut self, pixel: (f32, f32)) {
+        if let Some(depth) = pick::depth_at(&self.cam_data, &self.buffers, pixel) {
+            self.cam_data.focus_distance = depth;
+            self.cam_data.frames_without_move = 0.0;
+            println!("focus distance: {}", depth);
+        }
+    }
+
+    /// Writes the image the last frame displayed to a file, srgb encoded whether or not the
+    /// swapchain does that itself.
+    pub(crate) unsafe fn save_output(&self, path: &str) -> Result<()> {
+        self.device.device_wait_idle()?;
+
+        let extent = self.data.canvas_extent;
+        //four half floats a pixel
+        let size = (extent.width * extent.height * 8) as u64;
+        let (staging_buffer, staging_buffer_memory) = create_buffer(
+            &self.instance,
+            &self.device,
+            &self.data,
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        //the blit into the swapchain leaves the output image ready to be copied from
+        let command_buffer = begin_single_time_commands(&self.device, &self.data)?;
+
+        let region = vk::BufferImageCopy::builder()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(
+                vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .mip_level(0)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build(),
+            )
+            .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+            .image_extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            });
+
+        self.device.cmd_copy_image_to_buffer(
+            command_buffer,
+            self.data.image_buffers[0],
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            staging_buffer,
+            &[region],
+        );
+
+        end_single_time_commands(&self.device, &self.data, command_buffer)?;
+
+        let mut halves = vec![0_u16; (extent.width * extent.height * 4) as usize];
+        let memory = self.device.map_memory(staging_buffer_memory, 0, size, vk::MemoryMapFlags::empty())?;
+        memcpy(memory.cast(), halves.as_mut_ptr(), halves.len());
+        self.device.unmap_memory(staging_buffer_memory);
+
+        self.device.destroy_buffer(staging_buffer, None);
+        self.device.free_memory(staging_buffer_memory, None);
+
+        //the same that reaches the screen, encoded by the swapchain if the shader didn't
+        let encoding = self.cam_data.output_encoding;
+        let mut pixels = Vec::with_capacity(halves.len());
+        for pixel in halves.chunks_exact(4) {
+            for &value in &pixel[..3] {
+                pixels.push((encoding.displayed(half_to_f32(value)) * 255.0).round() as u8);
+            }
+            pixels.push(255);
+        }
+
+        image::RgbaImage::from_raw(extent.width, extent.height, pixels)
+            .ok_or_else(|| anyhow!("output image has the wrong size"))?
+            .save(path)?;
+        Ok(())
+    }
+
     /// Creates our Vulkan app.
     pub(crate) unsafe fn create(
         window: &Window,
-        cam_data: CamData,
+        mut cam_data: CamData,
         scene_info: SceneInfo,
         buffers: BufferSceneInfo,
     ) -> Result<Self> {
@@ -131,7 +259,17 @@ impl App {
         assert!(buffers.triangles.len() <= MAX_TRIANGLES);
         assert!(buffers.objects.len() <= MAX_OBJECTS);
         assert!(buffers.instances.len() <= MAX_INSTANCES);
+        assert!(buffers.materials.len() <= MAX_MATERIALS);
+        assert!(buffers.lights.len() <= MAX_LIGHT_TRIANGLES);
+        assert!(buffers.light_tree.len() <= 2 * MAX_LIGHT_TRIANGLES - 1);
+        assert!(buffers.density_grids.grids.len() <= MAX_DENSITY_GRIDS);
+        assert!(buffers.density_grids.densities.len() <= MAX_DENSITY_VOXELS);
         assert!(buffers.bvh.len() <= MAX_BVH_NODES);
+        assert!(buffers.environment.width as usize <= MAX_ENVIRONMENT_WIDTH);
+        assert!(buffers.environment.height as usize <= MAX_ENVIRONMENT_HEIGHT);
+        assert!(buffers.textures.width <= MAX_TEXTURE_SIZE);
+        assert!(buffers.textures.height <= MAX_TEXTURE_SIZE);
+        assert!(buffers.textures.layers <= MAX_TEXTURE_LAYERS);
 
         let loader = LibloadingLoader::new(LIBRARY)?;
         let entry = Entry::new(loader).map_err(|b| anyhow!("{}", b))?;
@@ -144,9 +282,15 @@ impl App {
         let device = create_logical_device(&entry, &instance, &mut data)?;
         create_swapchain(window, &instance, &device, &mut data)?;
         create_swapchain_image_views(&device, &mut data)?;
+        cam_data.output_encoding = output_encoding(data.swapchain_format);
+        data.canvas_extent = canvas_extent(cam_data.stereo);
+        cam_data.canvas_width = data.canvas_extent.width;
+        cam_data.canvas_height = data.canvas_extent.height;
         create_descriptor_set_layout(&device, &mut data)?;
         create_pipeline(&device, &mut data)?;
         create_command_pool(&instance, &device, &mut data)?;
+        create_environment_image(&instance, &device, &mut data, &buffers.environment)?;
+        create_texture_image(&instance, &device, &mut data, &buffers.textures)?;
         create_uniform_buffers(&instance, &device, &mut data)?;
         create_storage_buffers(&instance, &device, &mut data)?;
         create_image_buffers(&instance, &device, &mut data)?;
@@ -349,6 +493,127 @@ impl App {
             self.data.storage_buffers_memory[4],
         );
 
+        //---------------
+
+        let environment_cdf_memory = self.device.map_memory(
+            self.data.storage_buffers_memory[6],
+            0,
+            ENVIRONMENT_CDF_BUFFER_LEN as u64,
+            vk::MemoryMapFlags::empty(),
+        )?;
+        memcpy(
+            self.buffers.environment.cdf.as_ptr(),
+            environment_cdf_memory.cast(),
+            self.buffers.environment.cdf.len(),
+        );
+        self.device.unmap_memory(
+            self.data.storage_buffers_memory[6],
+        );
+
+        //---------------
+
+        let material_buffer_memory = self.device.map_memory(
+            self.data.storage_buffers_memory[7],
+            0,
+            MATERIAL_BUFFER_LEN as u64,
+            vk::MemoryMapFlags::empty(),
+        )?;
+        memcpy(
+            self.buffers.materials.as_ptr(),
+            material_buffer_memory.cast(),
+            self.buffers.materials.len(),
+        );
+        self.device.unmap_memory(
+            self.data.storage_buffers_memory[7],
+        );
+
+        //---------------
+
+        let light_buffer_memory = self.device.map_memory(
+            self.data.storage_buffers_memory[8],
+            0,
+            LIGHT_BUFFER_LEN as u64,
+            vk::MemoryMapFlags::empty(),
+        )?;
+        memcpy(
+            self.buffers.lights.as_ptr(),
+            light_buffer_memory.cast(),
+            self.buffers.lights.len(),
+        );
+        self.device.unmap_memory(
+            self.data.storage_buffers_memory[8],
+        );
+
+        //---------------
+
+        let light_tree_memory = self.device.map_memory(
+            self.data.storage_buffers_memory[9],
+            0,
+            LIGHT_TREE_BUFFER_LEN as u64,
+            vk::MemoryMapFlags::empty(),
+        )?;
+        memcpy(
+            self.buffers.light_tree.as_ptr(),
+            light_tree_memory.cast(),
+            self.buffers.light_tree.len(),
+        );
+        self.device.unmap_memory(
+            self.data.storage_buffers_memory[9],
+        );
+
+        //---------------
+
+        let density_grid_memory = self.device.map_memory(
+            self.data.storage_buffers_memory[10],
+            0,
+            DENSITY_GRID_BUFFER_LEN as u64,
+            vk::MemoryMapFlags::empty(),
+        )?;
+        memcpy(
+            self.buffers.density_grids.grids.as_ptr(),
+            density_grid_memory.cast(),
+            self.buffers.density_grids.grids.len(),
+        );
+        self.device.unmap_memory(
+            self.data.storage_buffers_memory[10],
+        );
+
+        //---------------
+
+        let density_memory = self.device.map_memory(
+            self.data.storage_buffers_memory[11],
+            0,
+            DENSITY_BUFFER_LEN as u64,
+            vk::MemoryMapFlags::empty(),
+        )?;
+        memcpy(
+            self.buffers.density_grids.densities.as_ptr(),
+            density_memory.cast(),
+            self.buffers.density_grids.densities.len(),
+        );
+        self.device.unmap_memory(
+            self.data.storage_buffers_memory[11],
+        );
+
+        //---------------
+
+        //the histogram this frame fills starts out empty, the other one is left to meter from
+        let histogram_offset = (self.cam_data.frame % 2) as usize * HISTOGRAM_BINS as usize;
+        let histogram_memory = self.device.map_memory(
+            self.data.storage_buffers_memory[12],
+            (histogram_offset * std::mem::size_of::<u32>()) as u64,
+            (HISTOGRAM_BINS as usize * std::mem::size_of::<u32>()) as u64,
+            vk::MemoryMapFlags::empty(),
+        )?;
+        std::ptr::write_bytes(
+            histogram_memory.cast::<u32>(),
+            0,
+            HISTOGRAM_BINS as usize,
+        );
+        self.device.unmap_memory(
+            self.data.storage_buffers_memory[12],
+        );
+
         Ok(())
     }
 
@@ -359,6 +624,7 @@ impl App {
         self.destroy_swapchain();
         create_swapchain(window, &self.instance, &self.device, &mut self.data)?;
         create_swapchain_image_views(&self.device, &mut self.data)?;
+        self.cam_data.output_encoding = output_encoding(self.data.swapchain_format);
         create_uniform_buffers(&self.instance, &self.device, &mut self.data)?;
         create_storage_buffers(&self.instance, &self.device, &mut self.data)?;
         create_image_buffers(&self.instance, &self.device, &mut self.data)?;
@@ -379,6 +645,14 @@ impl App {
         self.data.in_flight_fences.iter().for_each(|f| self.device.destroy_fence(*f, None));
         self.data.render_finished_semaphores.iter().for_each(|s| self.device.destroy_semaphore(*s, None));
         self.data.image_available_semaphores.iter().for_each(|s| self.device.destroy_semaphore(*s, None));
+        self.device.destroy_sampler(self.data.environment_sampler, None);
+        self.device.destroy_image_view(self.data.environment_image_view, None);
+        self.device.destroy_image(self.data.environment_image, None);
+        self.device.free_memory(self.data.environment_image_memory, None);
+        self.data.texture_samplers.iter().for_each(|s| self.device.destroy_sampler(*s, None));
+        self.device.destroy_image_view(self.data.texture_image_view, None);
+        self.device.destroy_image(self.data.texture_image, None);
+        self.device.free_memory(self.data.texture_image_memory, None);
         self.device.destroy_command_pool(self.data.command_pool, None);
         self.device.destroy_descriptor_set_layout(self.data.descriptor_set_layout, None);
         self.device.destroy_device(None);
@@ -401,6 +675,7 @@ impl App {
         self.data.image_buffers_memory.iter().for_each(|m| self.device.free_memory(*m, None));
         self.data.uniform_buffers.iter().for_each(|b| self.device.destroy_buffer(*b, None));
         self.data.storage_buffers.iter().for_each(|b| self.device.destroy_buffer(*b, None));
+        self.data.image_views.iter().for_each(|v| self.device.destroy_image_view(*v, None));
         self.data.image_buffers.iter().for_each(|b| self.device.destroy_image(*b, None));
         self.data.framebuffers.iter().for_each(|f| self.device.destroy_framebuffer(*f, None));
         self.data.swapchain_image_views.iter().for_each(|v| self.device.destroy_image_view(*v, None));
@@ -422,6 +697,8 @@ struct AppData {
     // Swapchain
     swapchain_format: vk::Format,
     swapchain_extent: vk::Extent2D,
+    /// of the accumulation buffer and the output image, twice the window along one side for stereo
+    canvas_extent: vk::Extent2D,
     swapchain: vk::SwapchainKHR,
     swapchain_images: Vec<vk::Image>,
     swapchain_image_views: Vec<vk::ImageView>,
@@ -440,6 +717,16 @@ struct AppData {
     image_buffers: Vec<vk::Image>,
     image_buffers_memory: Vec<vk::DeviceMemory>,
     image_views: Vec<vk::ImageView>,
+    // Environment Map
+    environment_image: vk::Image,
+    environment_image_memory: vk::DeviceMemory,
+    environment_image_view: vk::ImageView,
+    environment_sampler: vk::Sampler,
+    // Textures
+    texture_image: vk::Image,
+    texture_image_memory: vk::DeviceMemory,
+    texture_image_view: vk::ImageView,
+    texture_samplers: Vec<vk::Sampler>,
 
     descriptor_pool: vk::DescriptorPool,
     descriptor_sets: Vec<vk::DescriptorSet>,
@@ -761,7 +1048,7 @@ unsafe fn create_swapchain(
         .image_color_space(surface_format.color_space)
         .image_extent(extent)
         .image_array_layers(1)
-        .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+        .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST)
         .image_sharing_mode(image_sharing_mode)
         .queue_family_indices(&queue_family_indices)
         .pre_transform(support.capabilities.current_transform)
@@ -779,17 +1066,62 @@ unsafe fn create_swapchain(
     Ok(())
 }
 
+/// Prefers a unorm swapchain the shader can encode srgb into itself, an srgb one encodes
+/// whatever is blitted into it on its own.
 fn get_swapchain_surface_format(formats: &[vk::SurfaceFormatKHR]) -> vk::SurfaceFormatKHR {
-    formats
+    let preferred = [
+        vk::Format::B8G8R8A8_UNORM,
+        vk::Format::R8G8B8A8_UNORM,
+        vk::Format::B8G8R8A8_SRGB,
+        vk::Format::R8G8B8A8_SRGB,
+    ];
+    preferred
         .iter()
-        .cloned()
-        .find(|f| {
-            f.format == vk::Format::B8G8R8A8_SRGB
-                && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+        .find_map(|&format| {
+            formats.iter().cloned().find(|f| {
+                f.format == format && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+            })
         })
         .unwrap_or_else(|| formats[0])
 }
 
+//ieee 754 half precision, as the output image stores it
+fn half_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2.0_f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2.0_f32.powi(exponent - 15),
+    }
+}
+
+/// Size of the canvas, stereo puts the eyes next to or above each other at the full resolution.
+fn canvas_extent(stereo: StereoLayout) -> vk::Extent2D {
+    let (width, height) = (WIDTH as u32, HEIGHT as u32);
+    match stereo {
+        StereoLayout::Mono => vk::Extent2D { width, height },
+        StereoLayout::SideBySide => vk::Extent2D { width: 2 * width, height },
+        StereoLayout::OverUnder => vk::Extent2D { width, height: 2 * height },
+    }
+}
+
+fn accumulation_size(data: &AppData) -> u64 {
+    (data.canvas_extent.width * data.canvas_extent.height) as u64 * std::mem::size_of::<Vec4>() as u64
+}
+
+/// The one encode between the linear image and the screen, done by the shader unless the
+/// swapchain format does it already.
+fn output_encoding(format: vk::Format) -> OutputEncoding {
+    if format == vk::Format::B8G8R8A8_SRGB || format == vk::Format::R8G8B8A8_SRGB {
+        OutputEncoding::Linear
+    } else {
+        OutputEncoding::Srgb
+    }
+}
+
 fn get_swapchain_present_mode(present_modes: &[vk::PresentModeKHR]) -> vk::PresentModeKHR {
     present_modes
         .iter()
@@ -1001,6 +1333,40 @@ unsafe fn create_command_pool(
     Ok(())
 }
 
+unsafe fn begin_single_time_commands(device: &Device, data: &AppData) -> Result<vk::CommandBuffer> {
+    let info = vk::CommandBufferAllocateInfo::builder()
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_pool(data.command_pool)
+        .command_buffer_count(1);
+
+    let command_buffer = device.allocate_command_buffers(&info)?[0];
+
+    let info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+    device.begin_command_buffer(command_buffer, &info)?;
+
+    Ok(command_buffer)
+}
+
+unsafe fn end_single_time_commands(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+) -> Result<()> {
+    device.end_command_buffer(command_buffer)?;
+
+    let command_buffers = &[command_buffer];
+    let info = vk::SubmitInfo::builder().command_buffers(command_buffers);
+
+    device.queue_submit(data.compute_queue, &[info], vk::Fence::null())?;
+    device.queue_wait_idle(data.compute_queue)?;
+
+    device.free_command_buffers(data.command_pool, command_buffers);
+
+    Ok(())
+}
+
 //================================================
 // Command Buffers
 //================================================
@@ -1036,8 +1402,8 @@ unsafe fn create_command_buffers(device: &Device, data: &mut AppData) -> Result<
             &[],
         );
 
-        let group_count_x = WIDTH.div_ceil(16) as u32;
-        let group_count_y = HEIGHT.div_ceil(16) as u32;
+        let group_count_x = data.canvas_extent.width.div_ceil(16);
+        let group_count_y = data.canvas_extent.height.div_ceil(16);
         let group_count_z = 1;
 
         device.cmd_dispatch(command_buffer, group_count_x, group_count_y, group_count_z);
@@ -1064,29 +1430,43 @@ unsafe fn create_command_buffers(device: &Device, data: &mut AppData) -> Result<
             vk::AccessFlags::TRANSFER_WRITE,
         );
 
-        // Copy from compute image to swapchain image
+        // Blit from compute image to swapchain image, unlike a copy it converts between the
+        // channel orders, srgb swapchains encode on the way in and stereo canvases get scaled
+        // down to the window
         let image_subresource = vk::ImageSubresourceLayers::builder()
             .aspect_mask(vk::ImageAspectFlags::COLOR)
             .mip_level(0)
             .base_array_layer(0)
             .layer_count(1);
 
-        let image_copy = vk::ImageCopy::builder()
+        let image_blit = vk::ImageBlit::builder()
             .src_subresource(*image_subresource)
+            .src_offsets([
+                vk::Offset3D { x: 0, y: 0, z: 0 },
+                vk::Offset3D {
+                    x: data.canvas_extent.width as i32,
+                    y: data.canvas_extent.height as i32,
+                    z: 1,
+                },
+            ])
             .dst_subresource(*image_subresource)
-            .extent(vk::Extent3D {
-                width: WIDTH as u32,
-                height: HEIGHT as u32,
-                depth: 1,
-            });
-
-        device.cmd_copy_image(
+            .dst_offsets([
+                vk::Offset3D { x: 0, y: 0, z: 0 },
+                vk::Offset3D {
+                    x: data.swapchain_extent.width as i32,
+                    y: data.swapchain_extent.height as i32,
+                    z: 1,
+                },
+            ]);
+
+        device.cmd_blit_image(
             command_buffer,
             data.image_buffers[0],
             vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
             data.swapchain_images[i],
             vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            &[*image_copy],
+            &[*image_blit],
+            vk::Filter::LINEAR,
         );
 
         // Transition swapchain image to PRESENT_SRC_KHR
@@ -1200,6 +1580,66 @@ unsafe fn create_descriptor_set_layout(device: &Device, data: &mut AppData) -> R
         .descriptor_count(1)
         .stage_flags(vk::ShaderStageFlags::COMPUTE);
 
+    let environment_map_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(9)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let storage_buffer_binding_7 = vk::DescriptorSetLayoutBinding::builder()
+        .binding(10)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let texture_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(11)
+        .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let texture_sampler_binding = vk::DescriptorSetLayoutBinding::builder()
+        .binding(12)
+        .descriptor_type(vk::DescriptorType::SAMPLER)
+        .descriptor_count(NUM_SAMPLER_DESCRIPTORS)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let storage_buffer_binding_8 = vk::DescriptorSetLayoutBinding::builder()
+        .binding(13)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let storage_buffer_binding_9 = vk::DescriptorSetLayoutBinding::builder()
+        .binding(14)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let storage_buffer_binding_10 = vk::DescriptorSetLayoutBinding::builder()
+        .binding(15)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let storage_buffer_binding_11 = vk::DescriptorSetLayoutBinding::builder()
+        .binding(16)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let storage_buffer_binding_12 = vk::DescriptorSetLayoutBinding::builder()
+        .binding(17)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
+    let storage_buffer_binding_13 = vk::DescriptorSetLayoutBinding::builder()
+        .binding(18)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .descriptor_count(1)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+
     let bindings = &[
         ubo_binding_1,
         ubo_binding_2,
@@ -1210,6 +1650,16 @@ unsafe fn create_descriptor_set_layout(device: &Device, data: &mut AppData) -> R
         storage_buffer_binding_5,
         storage_buffer_binding_6,
         image_buffer_binding_2,
+        environment_map_binding,
+        storage_buffer_binding_7,
+        texture_binding,
+        texture_sampler_binding,
+        storage_buffer_binding_8,
+        storage_buffer_binding_9,
+        storage_buffer_binding_10,
+        storage_buffer_binding_11,
+        storage_buffer_binding_12,
+        storage_buffer_binding_13,
     ];
     let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
 
@@ -1218,6 +1668,282 @@ unsafe fn create_descriptor_set_layout(device: &Device, data: &mut AppData) -> R
     Ok(())
 }
 
+//================================================
+// Sampled Images
+//================================================
+
+unsafe fn create_environment_image(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    environment: &EnvironmentMap,
+) -> Result<()> {
+    (
+        data.environment_image,
+        data.environment_image_memory,
+        data.environment_image_view,
+    ) = create_sampled_image(
+        instance,
+        device,
+        data,
+        vk::Format::R32G32B32A32_SFLOAT,
+        vk::ImageViewType::_2D,
+        (environment.width, environment.height, 1),
+        &environment.pixels,
+    )?;
+
+    //wraps around horizontally but not over the poles
+    data.environment_sampler = create_sampler(
+        device,
+        vk::SamplerAddressMode::REPEAT,
+        vk::SamplerAddressMode::CLAMP_TO_EDGE,
+    )?;
+
+    Ok(())
+}
+
+unsafe fn create_texture_image(
+    instance: &Instance,
+    device: &Device,
+    data: &mut AppData,
+    textures: &TextureArray,
+) -> Result<()> {
+    //stored as plain unorm, colour textures are decoded from srgb in the shader so that
+    //roughness and other data textures can share the array
+    (
+        data.texture_image,
+        data.texture_image_memory,
+        data.texture_image_view,
+    ) = create_sampled_image(
+        instance,
+        device,
+        data,
+        vk::Format::R8G8B8A8_UNORM,
+        vk::ImageViewType::_2D_ARRAY,
+        (textures.width, textures.height, textures.layers),
+        &textures.pixels,
+    )?;
+
+    data.texture_samplers = [
+        vk::SamplerAddressMode::REPEAT,
+        vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        vk::SamplerAddressMode::MIRRORED_REPEAT,
+    ]
+    .iter()
+    .map(|&mode| create_sampler(device, mode, mode))
+    .collect::<Result<Vec<_>>>()?;
+
+    Ok(())
+}
+
+/// Uploads `pixels` into a device local image with `layers` layers of `extent` texels each
+/// and returns it in SHADER_READ_ONLY_OPTIMAL layout together with its memory and view.
+unsafe fn create_sampled_image<T>(
+    instance: &Instance,
+    device: &Device,
+    data: &AppData,
+    format: vk::Format,
+    view_type: vk::ImageViewType,
+    (width, height, layers): (u32, u32, u32),
+    pixels: &[T],
+) -> Result<(vk::Image, vk::DeviceMemory, vk::ImageView)> {
+    let size = std::mem::size_of_val(pixels) as u64;
+
+    let (staging_buffer, staging_buffer_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        size,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )?;
+
+    let memory = device.map_memory(staging_buffer_memory, 0, size, vk::MemoryMapFlags::empty())?;
+    memcpy(pixels.as_ptr(), memory.cast(), pixels.len());
+    device.unmap_memory(staging_buffer_memory);
+
+    let image_create_info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::_2D)
+        .format(format)
+        .extent(vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        })
+        .mip_levels(1)
+        .array_layers(layers)
+        .samples(vk::SampleCountFlags::_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .initial_layout(vk::ImageLayout::UNDEFINED);
+
+    let image = device.create_image(&image_create_info, None)?;
+
+    let mem_requirements = device.get_image_memory_requirements(image);
+    let alloc_info = vk::MemoryAllocateInfo::builder()
+        .allocation_size(mem_requirements.size)
+        .memory_type_index(find_memory_type(
+            instance,
+            data,
+            mem_requirements.memory_type_bits,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?);
+
+    let image_memory = device.allocate_memory(&alloc_info, None)?;
+    device.bind_image_memory(image, image_memory, 0)?;
+
+    // Upload
+    let command_buffer = begin_single_time_commands(device, data)?;
+
+    transition_image_layout(
+        device,
+        command_buffer,
+        image,
+        layers,
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+    );
+
+    let region = vk::BufferImageCopy::builder()
+        .buffer_offset(0)
+        .buffer_row_length(0)
+        .buffer_image_height(0)
+        .image_subresource(
+            vk::ImageSubresourceLayers::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .mip_level(0)
+                .base_array_layer(0)
+                .layer_count(layers)
+                .build(),
+        )
+        .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+        .image_extent(vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        });
+
+    device.cmd_copy_buffer_to_image(
+        command_buffer,
+        staging_buffer,
+        image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &[region],
+    );
+
+    transition_image_layout(
+        device,
+        command_buffer,
+        image,
+        layers,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    );
+
+    end_single_time_commands(device, data, command_buffer)?;
+
+    device.destroy_buffer(staging_buffer, None);
+    device.free_memory(staging_buffer_memory, None);
+
+    // View
+    let view_info = vk::ImageViewCreateInfo::builder()
+        .image(image)
+        .view_type(view_type)
+        .format(format)
+        .subresource_range(
+            vk::ImageSubresourceRange::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .base_mip_level(0)
+                .level_count(1)
+                .base_array_layer(0)
+                .layer_count(layers)
+                .build(),
+        );
+
+    let image_view = device.create_image_view(&view_info, None)?;
+
+    Ok((image, image_memory, image_view))
+}
+
+unsafe fn create_sampler(
+    device: &Device,
+    address_mode_u: vk::SamplerAddressMode,
+    address_mode_v: vk::SamplerAddressMode,
+) -> Result<vk::Sampler> {
+    let sampler_info = vk::SamplerCreateInfo::builder()
+        .mag_filter(vk::Filter::LINEAR)
+        .min_filter(vk::Filter::LINEAR)
+        .address_mode_u(address_mode_u)
+        .address_mode_v(address_mode_v)
+        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+        .anisotropy_enable(false)
+        .max_anisotropy(1.0)
+        .border_color(vk::BorderColor::FLOAT_OPAQUE_BLACK)
+        .unnormalized_coordinates(false)
+        .compare_enable(false)
+        .compare_op(vk::CompareOp::ALWAYS)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+        .mip_lod_bias(0.0)
+        .min_lod(0.0)
+        .max_lod(0.0);
+
+    Ok(device.create_sampler(&sampler_info, None)?)
+}
+
+unsafe fn transition_image_layout(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    layer_count: u32,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+) {
+    let (src_access_mask, dst_access_mask, src_stage_mask, dst_stage_mask) =
+        match (old_layout, new_layout) {
+            (vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL) => (
+                vk::AccessFlags::empty(),
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+            ),
+            _ => (
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::SHADER_READ,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+            ),
+        };
+
+    let barrier = vk::ImageMemoryBarrier::builder()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(
+            vk::ImageSubresourceRange::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .base_mip_level(0)
+                .level_count(1)
+                .base_array_layer(0)
+                .layer_count(layer_count)
+                .build(),
+        )
+        .src_access_mask(src_access_mask)
+        .dst_access_mask(dst_access_mask);
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        src_stage_mask,
+        dst_stage_mask,
+        vk::DependencyFlags::empty(),
+        &[] as &[vk::MemoryBarrier],
+        &[] as &[vk::BufferMemoryBarrier],
+        &[*barrier],
+    );
+}
+
 //================================================
 // Sync Objects
 //================================================
@@ -1399,7 +2125,91 @@ unsafe fn create_storage_buffers(
         instance,
         device,
         data,
-        (WIDTH * HEIGHT * std::mem::size_of::<Vec4>()) as u64,
+        accumulation_size(data),
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )?;
+
+    data.storage_buffers.push(storage_buffer);
+    data.storage_buffers_memory.push(storage_buffer_memory);
+
+    let (storage_buffer, storage_buffer_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        ENVIRONMENT_CDF_BUFFER_LEN as u64,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )?;
+
+    data.storage_buffers.push(storage_buffer);
+    data.storage_buffers_memory.push(storage_buffer_memory);
+
+    let (storage_buffer, storage_buffer_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        MATERIAL_BUFFER_LEN as u64,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )?;
+
+    data.storage_buffers.push(storage_buffer);
+    data.storage_buffers_memory.push(storage_buffer_memory);
+
+    let (storage_buffer, storage_buffer_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        LIGHT_BUFFER_LEN as u64,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )?;
+
+    data.storage_buffers.push(storage_buffer);
+    data.storage_buffers_memory.push(storage_buffer_memory);
+
+    let (storage_buffer, storage_buffer_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        LIGHT_TREE_BUFFER_LEN as u64,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )?;
+
+    data.storage_buffers.push(storage_buffer);
+    data.storage_buffers_memory.push(storage_buffer_memory);
+
+    let (storage_buffer, storage_buffer_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        DENSITY_GRID_BUFFER_LEN as u64,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )?;
+
+    data.storage_buffers.push(storage_buffer);
+    data.storage_buffers_memory.push(storage_buffer_memory);
+
+    let (storage_buffer, storage_buffer_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        DENSITY_BUFFER_LEN as u64,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
+    )?;
+
+    data.storage_buffers.push(storage_buffer);
+    data.storage_buffers_memory.push(storage_buffer_memory);
+
+    let (storage_buffer, storage_buffer_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        HISTOGRAM_BUFFER_LEN as u64,
         vk::BufferUsageFlags::STORAGE_BUFFER,
         vk::MemoryPropertyFlags::HOST_COHERENT | vk::MemoryPropertyFlags::HOST_VISIBLE,
     )?;
@@ -1417,16 +2227,17 @@ unsafe fn create_image_buffers(
 ) -> Result<()> {
     data.image_buffers.clear();
     data.image_buffers_memory.clear();
+    data.image_views.clear();
 
     let image_extent = vk::Extent3D {
-        width: WIDTH as u32,
-        height: HEIGHT as u32,
+        width: data.canvas_extent.width,
+        height: data.canvas_extent.height,
         depth: 1,
     };
 
     let image_create_info = vk::ImageCreateInfo::builder()
         .image_type(vk::ImageType::_2D)
-        .format(vk::Format::R32G32B32_SFLOAT)
+        .format(OUTPUT_FORMAT)
         .extent(image_extent)
         .mip_levels(1)
         .array_layers(1)
@@ -1457,7 +2268,7 @@ unsafe fn create_image_buffers(
     let view_info = vk::ImageViewCreateInfo::builder()
         .image(image)
         .view_type(vk::ImageViewType::_2D)
-        .format(vk::Format::R8G8B8A8_UNORM) // or whatever you're using
+        .format(OUTPUT_FORMAT)
         .subresource_range(
             vk::ImageSubresourceRange::builder()
                 .aspect_mask(vk::ImageAspectFlags::COLOR)
@@ -1509,10 +2320,36 @@ unsafe fn create_descriptor_pool(device: &Device, data: &mut AppData) -> Result<
         .type_(vk::DescriptorType::STORAGE_IMAGE)
         .descriptor_count(NUM_IMAGE_DESCRIPTORS);
 
-    let pool_sizes = &[ubo_size, storage_size];
+    let combined_sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(NUM_COMBINED_SAMPLER_DESCRIPTORS);
+
+    let sampled_image_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLED_IMAGE)
+        .descriptor_count(NUM_SAMPLED_IMAGE_DESCRIPTORS);
+
+    let sampler_size = vk::DescriptorPoolSize::builder()
+        .type_(vk::DescriptorType::SAMPLER)
+        .descriptor_count(NUM_SAMPLER_DESCRIPTORS);
+
+    let pool_sizes = &[
+        ubo_size,
+        storage_size,
+        image_size,
+        combined_sampler_size,
+        sampled_image_size,
+        sampler_size,
+    ];
     let info = vk::DescriptorPoolCreateInfo::builder()
         .pool_sizes(pool_sizes)
-        .max_sets(NUM_UNIFORM_DESCRIPTORS + NUM_STORAGE_DESCRIPTORS + NUM_IMAGE_DESCRIPTORS);
+        .max_sets(
+            NUM_UNIFORM_DESCRIPTORS
+                + NUM_STORAGE_DESCRIPTORS
+                + NUM_IMAGE_DESCRIPTORS
+                + NUM_COMBINED_SAMPLER_DESCRIPTORS
+                + NUM_SAMPLED_IMAGE_DESCRIPTORS
+                + NUM_SAMPLER_DESCRIPTORS,
+        );
 
     data.descriptor_pool = device.create_descriptor_pool(&info, None)?;
 
@@ -1572,7 +2409,42 @@ unsafe fn create_descriptor_sets(device: &Device, data: &mut AppData) -> Result<
     let acc_buffer_info = vk::DescriptorBufferInfo::builder()
         .buffer(data.storage_buffers[5])
         .offset(0)
-        .range((WIDTH * HEIGHT * std::mem::size_of::<Vec4>()) as u64);
+        .range(accumulation_size(data));
+
+    let environment_cdf_info = vk::DescriptorBufferInfo::builder()
+        .buffer(data.storage_buffers[6])
+        .offset(0)
+        .range(ENVIRONMENT_CDF_BUFFER_LEN as u64);
+
+    let material_info = vk::DescriptorBufferInfo::builder()
+        .buffer(data.storage_buffers[7])
+        .offset(0)
+        .range(MATERIAL_BUFFER_LEN as u64);
+
+    let light_info = vk::DescriptorBufferInfo::builder()
+        .buffer(data.storage_buffers[8])
+        .offset(0)
+        .range(LIGHT_BUFFER_LEN as u64);
+
+    let light_tree_info = vk::DescriptorBufferInfo::builder()
+        .buffer(data.storage_buffers[9])
+        .offset(0)
+        .range(LIGHT_TREE_BUFFER_LEN as u64);
+
+    let density_grid_info = vk::DescriptorBufferInfo::builder()
+        .buffer(data.storage_buffers[10])
+        .offset(0)
+        .range(DENSITY_GRID_BUFFER_LEN as u64);
+
+    let density_info = vk::DescriptorBufferInfo::builder()
+        .buffer(data.storage_buffers[11])
+        .offset(0)
+        .range(DENSITY_BUFFER_LEN as u64);
+
+    let histogram_info = vk::DescriptorBufferInfo::builder()
+        .buffer(data.storage_buffers[12])
+        .offset(0)
+        .range(HISTOGRAM_BUFFER_LEN as u64);
 
     //----------IMAGE BUFFERS----------
     let res_image_info = vk::DescriptorImageInfo::builder()
@@ -1580,6 +2452,22 @@ unsafe fn create_descriptor_sets(device: &Device, data: &mut AppData) -> Result<
         .image_view(data.image_views[0])
         .sampler(vk::Sampler::null());
 
+    let environment_image_info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(data.environment_image_view)
+        .sampler(data.environment_sampler);
+
+    let texture_image_info = vk::DescriptorImageInfo::builder()
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image_view(data.texture_image_view)
+        .sampler(vk::Sampler::null());
+
+    let texture_sampler_infos = data
+        .texture_samplers
+        .iter()
+        .map(|&sampler| vk::DescriptorImageInfo::builder().sampler(sampler).build())
+        .collect::<Vec<_>>();
+
     let writes = [
         vk::WriteDescriptorSet::builder()
             .dst_set(data.descriptor_sets[0])
@@ -1606,6 +2494,66 @@ unsafe fn create_descriptor_sets(device: &Device, data: &mut AppData) -> Result<
             .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
             .image_info(&[res_image_info])
             .build(),
+        vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[0])
+            .dst_binding(9)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&[environment_image_info])
+            .build(),
+        vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[0])
+            .dst_binding(10)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&[environment_cdf_info])
+            .build(),
+        vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[0])
+            .dst_binding(11)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .image_info(&[texture_image_info])
+            .build(),
+        vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[0])
+            .dst_binding(12)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .image_info(&texture_sampler_infos)
+            .build(),
+        vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[0])
+            .dst_binding(13)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&[material_info])
+            .build(),
+        vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[0])
+            .dst_binding(14)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&[light_info])
+            .build(),
+        vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[0])
+            .dst_binding(15)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&[light_tree_info])
+            .build(),
+        vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[0])
+            .dst_binding(16)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&[density_grid_info])
+            .build(),
+        vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[0])
+            .dst_binding(17)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&[density_info])
+            .build(),
+        vk::WriteDescriptorSet::builder()
+            .dst_set(data.descriptor_sets[0])
+            .dst_binding(18)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&[histogram_info])
+            .build(),
     ];
 
     //----------UPDATE DESCRIPTORS----------
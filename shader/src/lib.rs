@@ -10,14 +10,13 @@ use shared::*;
 #[allow(unused_imports)]
 use spirv_std::glam::{vec2, vec4, Vec2, Vec4};
 use spirv_std::image;
-use spirv_std::spirv;
+use spirv_std::image::{Image2d, Image2dArray, SampledImage};
+use spirv_std::memory::{Scope, Semantics};
+use spirv_std::{spirv, Sampler};
 pub mod modules;
 #[allow(unused_imports)]
 use modules::material::*;
 
-#[cfg(target_arch = "spirv")]
-use spirv_std::num_traits::Float;
-
 use crate::modules::get_seed;
 
 #[spirv(compute(threads(16, 16)))]
@@ -38,8 +37,21 @@ pub fn main(
         2D,
         sampled = false,
         __crate_root = crate,
-        format = rgba32f
+        format = rgba16f
     ),
+    #[spirv(uniform_constant, descriptor_set = 0, binding = 9)] environment_map: &SampledImage<
+        Image2d,
+    >,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 10)] environment_cdf: &[f32],
+    #[spirv(uniform_constant, descriptor_set = 0, binding = 11)] textures: &Image2dArray,
+    #[spirv(uniform_constant, descriptor_set = 0, binding = 12)] texture_samplers: &[Sampler; 3],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 13)] material_buffer: &[PrincipledMaterial],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 14)] light_buffer: &[LightTriangle],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 15)] light_tree: &[LightNode],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 16)] density_grids: &[DensityGrid],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 17)] densities: &[f32],
+    //two histograms, every frame fills one while metering from the other
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 18)] histogram: &mut [u32],
 ) {
     let objects = ObjectInfo {
         vertex_buffer,
@@ -47,6 +59,15 @@ pub fn main(
         object_buffer,
         instance_buffer,
         bvh_buffer,
+        environment_map,
+        environment_cdf,
+        textures,
+        texture_samplers,
+        material_buffer,
+        light_buffer,
+        light_tree,
+        density_grids,
+        densities,
     };
 
     if id.x >= data.canvas_width || id.y >= data.canvas_height {
@@ -68,9 +89,9 @@ pub fn main(
         &objects,
     );
 
-    let nan = rendered_color_3.x > 1000.0 || 
-        rendered_color_3.y > 1000.0 ||
-        rendered_color_3.z > 1000.0;
+    //large values are real, the sun alone is tens of thousands, exposure and tone mapping
+    //take care of those. only broken samples are left out
+    let nan = modules::is_vec_3_nan(&rendered_color_3);
 
     let rendered_color = Vec4::new(
         rendered_color_3.x,
@@ -83,11 +104,13 @@ pub fn main(
     let new_color;
 
     if data.frames_without_move < 0.5 {
-        acc_buffer[coord_index as usize] = rendered_color;
-        new_color = rendered_color;
+        let first_color = if nan { Vec4::new(0.0, 0.0, 0.0, 1.0) } else { rendered_color };
+        acc_buffer[coord_index as usize] = first_color;
+        new_color = first_color;
     } else {
+        //a broken sample is swapped for the average so far, which leaves the average as it is
         let acc_color = if nan {
-            Vec4::new(0.0, 1000000.0, 0.0, 1.0)
+            prev_color + prev_color / data.frames_without_move
         } else {
             prev_color + rendered_color
         };
@@ -97,13 +120,19 @@ pub fn main(
         new_color = acc_color / (data.frames_without_move + 1.0);
     }
 
-    //gamma correct
-    let present_color = Vec4::new(
-        new_color.x.powf(1.0 / 2.2),
-        new_color.y.powf(1.0 / 2.2),
-        new_color.z.powf(1.0 / 2.2),
-        1.0,
-    );
+    let bins = HISTOGRAM_BINS as usize;
+    let filled = (data.frame % 2) as usize * bins;
+    let metered = bins - filled;
+    let bin = modules::exposure::histogram_bin(modules::exposure::luminance(new_color.truncate()));
+    unsafe {
+        spirv_std::arch::atomic_i_increment::<u32, { Scope::Device as u32 }, { Semantics::NONE.bits() }>(
+            &mut histogram[filled + bin],
+        );
+    }
+    let exposed = new_color.truncate() * modules::exposure::exposure(data, histogram, metered);
+    let exposed = modules::tonemap::tone_map(exposed, data.tone_mapping);
+
+    let present_color = modules::tonemap::encode(exposed, data.output_encoding).extend(1.0);
 
     unsafe { res_output.write(id.xy(), present_color) }
 }
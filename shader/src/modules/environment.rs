@@ -0,0 +1,141 @@
+use core::f32::consts::PI;
+
+use super::rand_float;
+use super::ObjectInfo;
+use shared::glam::{Vec2, Vec3};
+use shared::SceneInfo;
+use spirv_std::image::{Image2d, SampledImage};
+#[allow(unused_imports)] //actually used for .sqrt because we don't allow std
+use spirv_std::num_traits::Float;
+
+/// Equirectangular HDR environment, importance sampled with the CDF built on the host.
+pub struct Environment<'a> {
+    pub map: &'a SampledImage<Image2d>,
+    /// marginal CDF over rows followed by the conditional CDF of every row
+    pub cdf: &'a [f32],
+    pub width: u32,
+    pub height: u32,
+    /// radians around the y axis
+    pub rotation: f32,
+    pub intensity: f32,
+}
+
+impl<'a> Environment<'a> {
+    pub fn new(scene_info: &SceneInfo, objects: &ObjectInfo<'a>) -> Self {
+        Environment {
+            map: objects.environment_map,
+            cdf: objects.environment_cdf,
+            width: scene_info.environment_width,
+            height: scene_info.environment_height,
+            rotation: scene_info.environment_rotation * PI / 180.0,
+            intensity: scene_info.environment_intensity,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.width > 0
+    }
+
+    fn rotate(&self, dir: Vec3, angle: f32) -> Vec3 {
+        let (sin, cos) = angle.sin_cos();
+        Vec3::new(dir.x * cos + dir.z * sin, dir.y, -dir.x * sin + dir.z * cos)
+    }
+
+    fn direction_to_uv(&self, dir: Vec3) -> Vec2 {
+        let local = self.rotate(dir.normalize(), -self.rotation);
+        let theta = local.y.clamp(-1.0, 1.0).acos();
+        let phi = local.z.atan2(local.x);
+        Vec2::new(phi / (2.0 * PI) + 0.5, theta / PI)
+    }
+
+    fn uv_to_direction(&self, uv: Vec2) -> Vec3 {
+        let phi = (uv.x - 0.5) * 2.0 * PI;
+        let theta = uv.y * PI;
+        let sin_theta = theta.sin();
+        let local = Vec3::new(sin_theta * phi.cos(), theta.cos(), sin_theta * phi.sin());
+        self.rotate(local, self.rotation)
+    }
+
+    pub fn radiance(&self, dir: Vec3) -> Vec3 {
+        let uv = self.direction_to_uv(dir);
+        self.map.sample_by_lod(uv, 0.0).truncate() * self.intensity
+    }
+
+    /// Picks a direction proportionally to the brightness of the map.
+    /// Returns the direction and its pdf with respect to solid angle.
+    pub fn sample(&self, seed: &mut u32) -> (Vec3, f32) {
+        let marginal_len = self.height as usize + 1;
+        let conditional_len = self.width as usize + 1;
+
+        let rand_row = rand_float(seed, (0.0, 1.0));
+        let row = find_interval(self.cdf, 0, marginal_len, rand_row);
+        let (row_pdf, row_offset) = segment(self.cdf, row, rand_row);
+
+        let rand_column = rand_float(seed, (0.0, 1.0));
+        let conditional_start = marginal_len + row * conditional_len;
+        let column = find_interval(self.cdf, conditional_start, conditional_len, rand_column);
+        let (column_pdf, column_offset) =
+            segment(self.cdf, conditional_start + column, rand_column);
+
+        let uv = Vec2::new(
+            (column as f32 + column_offset) / self.width as f32,
+            (row as f32 + row_offset) / self.height as f32,
+        );
+
+        let sin_theta = (uv.y * PI).sin();
+        if sin_theta <= 0.0 {
+            return (Vec3::new(0.0, 1.0, 0.0), 0.0);
+        }
+
+        //pdf over the unit square, then changed to solid angle
+        let pdf = row_pdf * self.height as f32 * column_pdf * self.width as f32;
+        (
+            self.uv_to_direction(uv),
+            pdf / (2.0 * PI * PI * sin_theta),
+        )
+    }
+
+    /// Density of [`Environment::sample`] returning `dir`.
+    pub fn pdf(&self, dir: Vec3) -> f32 {
+        let uv = self.direction_to_uv(dir);
+        let sin_theta = (uv.y * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        let row = ((uv.y * self.height as f32) as usize).min(self.height as usize - 1);
+        let column = ((uv.x * self.width as f32).max(0.0) as usize).min(self.width as usize - 1);
+        let conditional_start = self.height as usize + 1 + row * (self.width as usize + 1);
+        let row_pdf = self.cdf[row + 1] - self.cdf[row];
+        let column_pdf =
+            self.cdf[conditional_start + column + 1] - self.cdf[conditional_start + column];
+
+        row_pdf * self.height as f32 * column_pdf * self.width as f32 / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+/// Index `i` such that `cdf[start + i] <= value < cdf[start + i + 1]`.
+fn find_interval(cdf: &[f32], start: usize, len: usize, value: f32) -> usize {
+    let mut low = 0;
+    let mut high = len - 1;
+    while low + 1 < high {
+        let mid = (low + high) / 2;
+        if cdf[start + mid] <= value {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    low
+}
+
+/// Probability of the CDF segment starting at `index` and where `value` lies inside of it.
+fn segment(cdf: &[f32], index: usize, value: f32) -> (f32, f32) {
+    let probability = cdf[index + 1] - cdf[index];
+    let offset = if probability > 0.0 {
+        (value - cdf[index]) / probability
+    } else {
+        0.5
+    };
+    (probability, offset)
+}
@@ -0,0 +1,80 @@
+use shared::glam::Vec3;
+use shared::{CamData, ExposureMode, HISTOGRAM_BINS};
+#[allow(unused_imports)] //actually used for .log2 because we don't allow std
+use spirv_std::num_traits::Float;
+
+//range of log2 luminance the histogram covers, anything darker goes to the first bin
+const MIN_LOG_LUMINANCE: f32 = -12.0;
+const MAX_LOG_LUMINANCE: f32 = 8.0;
+
+//share of the darkest and of the brightest pixels left out of the metered average
+const LOW_PERCENTILE: f32 = 0.5;
+const HIGH_PERCENTILE: f32 = 0.95;
+
+pub fn luminance(color: Vec3) -> f32 {
+    color.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+/// Histogram bin of a pixel with `luminance`, the first one holding black pixels alone.
+pub fn histogram_bin(luminance: f32) -> usize {
+    if luminance <= 0.0 {
+        return 0;
+    }
+    let t = (luminance.log2() - MIN_LOG_LUMINANCE) / (MAX_LOG_LUMINANCE - MIN_LOG_LUMINANCE);
+    1 + ((t * (HISTOGRAM_BINS - 1) as f32) as i32).clamp(0, HISTOGRAM_BINS as i32 - 2) as usize
+}
+
+fn bin_log_luminance(bin: usize) -> f32 {
+    let t = (bin as f32 - 0.5) / (HISTOGRAM_BINS - 1) as f32;
+    MIN_LOG_LUMINANCE + t * (MAX_LOG_LUMINANCE - MIN_LOG_LUMINANCE)
+}
+
+/// Ev100 that puts the average luminance of the histogram starting at `offset` at middle grey,
+/// or `fallback` for an empty or all black one.
+/// Based on: Lagarde and de Rousiers, "Moving Frostbite to Physically Based Rendering 3.0"
+fn metered_ev100(histogram: &[u32], offset: usize, fallback: f32) -> f32 {
+    let mut count = 0;
+    let mut bin = 1;
+    while bin < HISTOGRAM_BINS as usize {
+        count += histogram[offset + bin];
+        bin += 1;
+    }
+    if count == 0 {
+        return fallback;
+    }
+
+    //average over the bins between the percentiles, partially counting the bins they fall in
+    let low = count as f32 * LOW_PERCENTILE;
+    let high = count as f32 * HIGH_PERCENTILE;
+    let mut below = 0.0;
+    let mut sum = 0.0;
+    let mut weight = 0.0;
+    let mut bin = 1;
+    while bin < HISTOGRAM_BINS as usize {
+        let pixels = histogram[offset + bin] as f32;
+        let start = below.max(low);
+        let end = (below + pixels).min(high);
+        if end > start {
+            sum += (end - start) * bin_log_luminance(bin);
+            weight += end - start;
+        }
+        below += pixels;
+        bin += 1;
+    }
+    let average = (sum / weight.max(1.0)).exp2();
+
+    //reflected light meter calibration constant of 12.5
+    (average * 100.0 / 12.5).log2()
+}
+
+/// What the accumulated radiance gets multiplied by before display. The histogram metered from
+/// the previous frame starts at `offset` in `histogram`.
+pub fn exposure(data: &CamData, histogram: &[u32], offset: usize) -> f32 {
+    let ev100 = if data.exposure_mode == ExposureMode::Auto {
+        metered_ev100(histogram, offset, data.ev100)
+    } else {
+        data.ev100
+    };
+    //saturation based sensitivity, a lens and sensor letting 1 / 1.2 of the light through
+    (data.exposure_compensation - ev100).exp2() / 1.2
+}
@@ -122,6 +122,40 @@ impl Mesh<'_> {
         }
     }
 
+    //any hit is enough, so children are pushed in whatever order they come and the first
+    //triangle inside t_clamp ends the traversal
+    fn occluded_bvh(&self, ray: &Ray, t_clamp: (f32, f32), backface_cull: bool) -> bool {
+        let mut stack = [0_u32; 32];
+        let mut stack_size = 1;
+        stack[0] = self.bvh_root;
+        while stack_size > 0 {
+            stack_size -= 1;
+            let node = &self.bvh_buffer[stack[stack_size] as usize];
+
+            let dist = ray.hits_bounding(&node.bounding_box);
+            if dist == f32::INFINITY || dist > t_clamp.1 {
+                continue;
+            }
+
+            if matches!(node.mode, shared::ChildTriangleMode::Children) {
+                stack[stack_size] = node.child_1_or_first_tri;
+                stack[stack_size + 1] = node.child_2_or_last_tri;
+                stack_size += 2;
+                continue;
+            }
+
+            let first_triangle = node.child_1_or_first_tri;
+            let last_triangle = node.child_2_or_last_tri;
+            for i in first_triangle..=last_triangle {
+                let t = self.hit_triangle(i, ray, t_clamp, backface_cull);
+                if !is_inf(t) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
     pub fn hit(
         &self,
         ray: &Ray,
@@ -132,6 +166,10 @@ impl Mesh<'_> {
     ) {
         self.hit_bvh(ray, t_clamp, record, instance_id, backface_cull)
     }
+
+    pub fn occluded(&self, ray: &Ray, t_clamp: (f32, f32), backface_cull: bool) -> bool {
+        self.occluded_bvh(ray, t_clamp, backface_cull)
+    }
 }
 
 fn triangle_ray_intersect(
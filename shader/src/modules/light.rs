@@ -0,0 +1,287 @@
+use core::f32::consts::PI;
+
+use super::orthonormal_basis;
+use super::rand_float;
+use super::ObjectInfo;
+use shared::glam::Vec3;
+use shared::ChildTriangleMode;
+use shared::LightNode;
+use shared::LightTriangle;
+use shared::sun_transmittance;
+use shared::SceneInfo;
+#[allow(unused_imports)] //actually used for .sqrt because we don't allow std
+use spirv_std::num_traits::Float;
+
+/// Directional light with a small angular size, built from the sun fields of [`SceneInfo`].
+pub struct Sun {
+    /// points towards the sun
+    pub direction: Vec3,
+    pub cos_radius: f32,
+    pub irradiance: Vec3,
+}
+
+impl Sun {
+    pub fn new(scene_info: &SceneInfo) -> Self {
+        let radius = (scene_info.sun_angular_diameter * PI / 180.0) / 2.0;
+        let direction = -scene_info.sun_orientation.normalize();
+        Sun {
+            direction,
+            cos_radius: radius.cos(),
+            irradiance: scene_info.sun_color
+                * scene_info.sun_intensity
+                * sun_transmittance(direction, scene_info.sky_turbidity),
+        }
+    }
+
+    pub fn solid_angle(&self) -> f32 {
+        2.0 * PI * (1.0 - self.cos_radius)
+    }
+
+    /// Picks a direction uniformly inside the cone the sun disk covers.
+    pub fn sample_direction(&self, seed: &mut u32) -> Vec3 {
+        let cos_theta = rand_float(seed, (self.cos_radius, 1.0));
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = rand_float(seed, (0.0, 2.0 * PI));
+        let (tangent, bitangent) = orthonormal_basis(self.direction);
+
+        (tangent * (phi.cos() * sin_theta)
+            + bitangent * (phi.sin() * sin_theta)
+            + self.direction * cos_theta)
+            .normalize()
+    }
+
+    /// Density of [`Sun::sample_direction`] returning `dir`.
+    pub fn pdf(&self, dir: Vec3) -> f32 {
+        if dir.normalize().dot(self.direction) >= self.cos_radius {
+            1.0 / self.solid_angle()
+        } else {
+            0.0
+        }
+    }
+
+    /// Radiance of the sun disk when looking along `ray_dir`, zero everywhere outside of it.
+    pub fn radiance(&self, ray_dir: Vec3) -> Vec3 {
+        if ray_dir.normalize().dot(self.direction) >= self.cos_radius {
+            self.irradiance / self.solid_angle()
+        } else {
+            Vec3::ZERO
+        }
+    }
+}
+
+/// Point on an emissive triangle picked by [`sample_light_triangle`].
+pub struct LightSample {
+    pub position: Vec3,
+    /// geometric normal of the triangle
+    pub normal: Vec3,
+    pub uv: (f32, f32),
+    pub instance_id: u32,
+    /// with respect to area, zero when no light could reach the shaded point
+    pub pdf: f32,
+}
+
+impl LightSample {
+    const NONE: Self = LightSample {
+        position: Vec3::ZERO,
+        normal: Vec3::ZERO,
+        uv: (0.0, 0.0),
+        instance_id: 0,
+        pdf: 0.0,
+    };
+}
+
+//cos(max(0, a - b)) from the sines and cosines of both
+fn cos_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32 {
+    if cos_a > cos_b {
+        1.0
+    } else {
+        cos_a * cos_b + sin_a * sin_b
+    }
+}
+
+//sin(max(0, a - b)) from the sines and cosines of both
+fn sin_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32 {
+    if cos_a > cos_b {
+        0.0
+    } else {
+        sin_a * cos_b - cos_a * sin_b
+    }
+}
+
+/// Conservative guess of how much light the triangles below `node` send to `pos`, on a
+/// surface facing `normal`. Emitters are two sided, so only the angle to the axis counts.
+fn importance(node: &LightNode, pos: Vec3, normal: Vec3) -> f32 {
+    let center = node.bounding_box.center();
+    let radius_2 = (node.bounding_box.max - center).length_squared();
+    let to_light = center - pos;
+    //inside the bounds the distance says nothing, don't let it blow up
+    let distance_2 = to_light.length_squared().max(radius_2);
+    let direction = to_light.normalize_or_zero();
+
+    //angle the bounding sphere covers as seen from pos
+    let (sin_theta_b, cos_theta_b) = if to_light.length_squared() <= radius_2 {
+        (0.0, -1.0)
+    } else {
+        let sin_2 = radius_2 / distance_2;
+        (sin_2.sqrt(), (1.0 - sin_2).max(0.0).sqrt())
+    };
+
+    //smallest angle between a normal in the cone and the way to pos
+    let cos_theta_w = direction.dot(node.axis).abs();
+    let sin_theta_w = (1.0 - cos_theta_w * cos_theta_w).max(0.0).sqrt();
+    let cos_theta_o = node.cos_theta_o;
+    let sin_theta_o = (1.0 - cos_theta_o * cos_theta_o).max(0.0).sqrt();
+    let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, cos_theta_o);
+    let sin_theta_x = sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, cos_theta_o);
+    let cos_theta = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+    if cos_theta <= node.cos_theta_e {
+        return 0.0;
+    }
+
+    //the same for the surface being lit, it may let light through so both sides count.
+    //points inside media have no normal and take light from everywhere
+    let cos_theta_surface = if normal == Vec3::ZERO {
+        1.0
+    } else {
+        let cos_theta_i = direction.dot(normal).abs();
+        let sin_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0).sqrt();
+        cos_sub_clamped(sin_theta_i, cos_theta_i, sin_theta_b, cos_theta_b)
+    };
+
+    node.power * cos_theta * cos_theta_surface / distance_2
+}
+
+/// Chance of going to the first child of `node` rather than the second one, from `pos`.
+fn first_child_probability(node: &LightNode, pos: Vec3, normal: Vec3, objects: &ObjectInfo) -> f32 {
+    let importance_1 = importance(&objects.light_tree[node.child_1_or_light as usize], pos, normal);
+    let importance_2 = importance(&objects.light_tree[node.child_2 as usize], pos, normal);
+    if importance_1 + importance_2 <= 0.0 {
+        return -1.0;
+    }
+    importance_1 / (importance_1 + importance_2)
+}
+
+fn triangle_area(light: &LightTriangle, objects: &ObjectInfo) -> f32 {
+    let transform = &objects.instance_buffer[light.instance_id as usize].transform;
+    let triangle = objects.triangle_buffer[light.triangle_id as usize];
+    let a = transform.transform_point3(objects.vertex_buffer[triangle.0 as usize].pos);
+    let b = transform.transform_point3(objects.vertex_buffer[triangle.1 as usize].pos);
+    let c = transform.transform_point3(objects.vertex_buffer[triangle.2 as usize].pos);
+    (b - a).cross(c - a).length() * 0.5
+}
+
+/// Walks down the light tree picking children by their importance to `pos`, then picks a point
+/// uniformly on the triangle it ends at. The light buffer must not be empty.
+pub fn sample_light_triangle(pos: Vec3, normal: Vec3, seed: &mut u32, objects: &ObjectInfo) -> LightSample {
+    let mut node = &objects.light_tree[0];
+    let mut probability = 1.0;
+    //the host keeps the tree under 32 levels
+    for _ in 0..32 {
+        if matches!(node.mode, ChildTriangleMode::Triangles) {
+            break;
+        }
+        let first = first_child_probability(node, pos, normal, objects);
+        if first < 0.0 {
+            return LightSample::NONE;
+        }
+        if rand_float(seed, (0.0, 1.0)) < first {
+            probability *= first;
+            node = &objects.light_tree[node.child_1_or_light as usize];
+        } else {
+            probability *= 1.0 - first;
+            node = &objects.light_tree[node.child_2 as usize];
+        }
+    }
+    let light = &objects.light_buffer[node.child_1_or_light as usize];
+
+    let transform = &objects.instance_buffer[light.instance_id as usize].transform;
+    let triangle = objects.triangle_buffer[light.triangle_id as usize];
+    let vert_1 = &objects.vertex_buffer[triangle.0 as usize];
+    let vert_2 = &objects.vertex_buffer[triangle.1 as usize];
+    let vert_3 = &objects.vertex_buffer[triangle.2 as usize];
+    let a = transform.transform_point3(vert_1.pos);
+    let b = transform.transform_point3(vert_2.pos);
+    let c = transform.transform_point3(vert_3.pos);
+
+    //uniform barycentric coordinates
+    let root = rand_float(seed, (0.0, 1.0)).sqrt();
+    let v = rand_float(seed, (0.0, 1.0));
+    let weights = Vec3::new(1.0 - root, root * (1.0 - v), root * v);
+
+    let cross = (b - a).cross(c - a);
+    let area = cross.length() * 0.5;
+    let uv = vert_1.uv * weights.x + vert_2.uv * weights.y + vert_3.uv * weights.z;
+
+    LightSample {
+        position: a * weights.x + b * weights.y + c * weights.z,
+        normal: cross.normalize(),
+        uv: (uv.x, uv.y),
+        instance_id: light.instance_id,
+        pdf: if area > 0.0 { probability / area } else { 0.0 },
+    }
+}
+
+/// Density with respect to solid angle of [`sample_light_triangle`] from `pos` landing on a
+/// point of the given triangle, `distance` away and seen at `cos_light` to its surface.
+/// Zero for triangles that aren't in the light buffer.
+pub fn light_triangle_pdf(
+    instance_id: u32,
+    triangle_id: u32,
+    pos: Vec3,
+    normal: Vec3,
+    distance: f32,
+    cos_light: f32,
+    scene_info: &SceneInfo,
+    objects: &ObjectInfo,
+) -> f32 {
+    if scene_info.num_lights == 0 || cos_light <= 0.0 {
+        return 0.0;
+    }
+
+    //the light buffer is sorted by instance and then triangle
+    let mut low = 0;
+    let mut high = scene_info.num_lights as usize;
+    while low < high {
+        let mid = (low + high) / 2;
+        let light = &objects.light_buffer[mid];
+        if light.instance_id < instance_id
+            || (light.instance_id == instance_id && light.triangle_id < triangle_id)
+        {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    if low == scene_info.num_lights as usize {
+        return 0.0;
+    }
+    let light = &objects.light_buffer[low];
+    if light.instance_id != instance_id || light.triangle_id != triangle_id {
+        return 0.0;
+    }
+
+    //retrace the choices that lead to it
+    let mut node = &objects.light_tree[0];
+    let mut probability = 1.0;
+    let mut depth = 0;
+    while depth < 32 && matches!(node.mode, ChildTriangleMode::Children) {
+        let first = first_child_probability(node, pos, normal, objects);
+        if first < 0.0 {
+            return 0.0;
+        }
+        if light.tree_path & (1 << depth) == 0 {
+            probability *= first;
+            node = &objects.light_tree[node.child_1_or_light as usize];
+        } else {
+            probability *= 1.0 - first;
+            node = &objects.light_tree[node.child_2 as usize];
+        }
+        depth += 1;
+    }
+
+    let area = triangle_area(light, objects);
+    if area <= 0.0 {
+        return 0.0;
+    }
+    probability / area * distance * distance / cos_light
+}
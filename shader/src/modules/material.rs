@@ -1,13 +1,19 @@
 use core::f32;
 use core::f32::consts::PI;
 
-use crate::modules::is_vec_3_nan;
-use crate::modules::trace::Ray;
-
 //use image::GenericImageView;
+use super::light::Sun;
+use super::microfacet::{fresnel_dielectric, fresnel_schlick, Frame, Ggx};
+use super::normal_map::{apply_bump_map, apply_normal_map, TangentFrame};
 use super::rand_float;
-use shared::acos_approx;
+use super::texture::{sample_texture, srgb_to_linear};
+use super::ObjectInfo;
 use shared::glam::Vec3;
+use shared::sky;
+use shared::SceneInfo;
+use shared::MaterialModel;
+use shared::PrincipledMaterial;
+use shared::TextureSlot;
 #[allow(unused_imports)] //actually used for .sqrt because we don't allow std
 use spirv_std::num_traits::Float;
 
@@ -15,474 +21,607 @@ pub enum RayReturnState {
     Absorb, //return color 0, 0, 0
     Stop,   //don't trace forward, but still color the thing
     Ray,
+    Pass,   //went through a surface that isn't there, doesn't count as a bounce
 }
 
-pub struct RayReturn {
-    pub state: RayReturnState,
-    pub direction: Vec3,
+/// Kind of lobe a direction was picked from, every kind has its own depth cap.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Lobe {
+    Diffuse,
+    Specular,
+    Transmission,
+    /// scattered inside a medium
+    Volume,
+    /// scattered inside the medium of a subsurface random walk, which takes many more steps
+    Subsurface,
 }
 
-fn rand_vec_in_unit_sphere(seed: &mut u32) -> Vec3 {
-    let phi = rand_float(seed, (0.0, 2.0 * PI));
-    let costheta = rand_float(seed, (-1.0, 1.0));
-
-    let theta = acos_approx(costheta);
-    let x = theta.sin() * phi.cos();
-    let y = theta.sin() * phi.sin();
-    let z = costheta;
-
-    Vec3::new(x, y, z)
+/// Direction picked by [`Material::sample`].
+pub struct BsdfSample {
+    pub direction: Vec3,
+    pub lobe: Lobe,
+    /// bsdf times the cosine of `direction` over `pdf`, what the throughput gets multiplied by
+    pub weight: Vec3,
+    /// with respect to solid angle, zero when the path got absorbed
+    pub pdf: f32,
 }
 
-fn diffuse_ray_direction(seed: &mut u32, normal: Vec3) -> Vec3 {
-    let rand_vec = rand_vec_in_unit_sphere(seed);
-    let res = rand_vec + normal;
+impl BsdfSample {
+    pub const ABSORBED: Self = BsdfSample {
+        direction: Vec3::ZERO,
+        lobe: Lobe::Diffuse,
+        weight: Vec3::ZERO,
+        pdf: 0.0,
+    };
 
-    if res.length_squared() < f32::EPSILON {
-        //if the random vector is too close to zero, just return the normal
-        normal
-    } else {
-        res.normalize()
+    pub fn absorbed(&self) -> bool {
+        self.pdf <= 0.0 || self.weight == Vec3::ZERO
     }
 }
 
-pub struct MaterialReturn {
-    pub ray_return_state: RayReturnState,
-    pub new_ray: Ray,
-    pub next_color: Vec3,
+/// Picks a direction around `normal` with a density of `cos / pi`.
+pub fn sample_cosine_hemisphere(normal: Vec3, seed: &mut u32) -> Vec3 {
+    let radius_2 = rand_float(seed, (0.0, 1.0));
+    let phi = rand_float(seed, (0.0, 2.0 * PI));
+    let radius = radius_2.sqrt();
+    let local = Vec3::new(radius * phi.cos(), radius * phi.sin(), (1.0 - radius_2).max(0.0).sqrt());
+    Frame::new(normal).to_world(local).normalize()
 }
 
+/// Surfaces are described by their bsdf. `wo` always points back along the ray that hit the
+/// surface and `wi` towards where light arrives from, both away from the hit point.
+/// `normal` is the shading normal, on whichever side the mesh put it.
 pub trait Material {
     fn backface_culling(&self) -> bool {
         true
     }
 
-    fn bxdf(
+    fn casts_shadow(&self) -> bool {
+        true
+    }
+
+    /// Normal the material is shaded with at `uv`, normal and bump mapped materials
+    /// perturb the interpolated one.
+    fn shading_normal(
+        &self,
+        frame: &TangentFrame,
+        _uv: (f32, f32),
+        _scene_info: &SceneInfo,
+        _objects: &ObjectInfo,
+    ) -> Vec3 {
+        frame.normal
+    }
+
+    /// Radiance the surface gives off by itself towards `wo`.
+    fn emission(&self, _wo: Vec3, _normal: Vec3, _uv: (f32, f32), _objects: &ObjectInfo) -> Vec3 {
+        Vec3::ZERO
+    }
+
+    /// Picks the next direction of the path, proportionally to the bsdf where possible.
+    fn sample(
         &self,
-        curr_color: Vec3,
-        in_ray: Ray,
+        wo: Vec3,
         normal: Vec3,
         uv: (f32, f32),
-        t: f32,
         seed: &mut u32,
-    ) -> MaterialReturn;
+        objects: &ObjectInfo,
+    ) -> BsdfSample;
+
+    /// Bsdf times the cosine of `wi`, so light arriving from `wi` times this is what leaves
+    /// towards `wo`.
+    fn eval(&self, wo: Vec3, wi: Vec3, normal: Vec3, uv: (f32, f32), objects: &ObjectInfo) -> Vec3;
+
+    /// Density with which [`Material::sample`] picks `wi`.
+    fn pdf(&self, wo: Vec3, wi: Vec3, normal: Vec3, uv: (f32, f32), objects: &ObjectInfo) -> f32;
+
+    /// Fraction of light absorbed per unit of distance travelled inside, for every channel.
+    fn absorption(&self) -> Vec3 {
+        Vec3::ZERO
+    }
 }
 
-pub struct GenericMaterial {
-    pub color: Vec3,
-    pub specular: f32,
-    pub specular_roughness: f32,
-    pub roughness: f32,
-    pub ior: f32,
+/// Reflects `wo` off a GGX microfacet picked among the ones visible to it, in local space.
+fn reflect_ggx(wo: Vec3, ggx: &Ggx, seed: &mut u32) -> Vec3 {
+    let h = ggx.sample_visible_normal(wo, seed);
+    h * (2.0 * wo.dot(h)) - wo
 }
 
-impl GenericMaterial {
-    fn reflect(in_dir: Vec3, normal: Vec3, roughness: f32, seed: &mut u32) -> Vec3 {
-        let dot_product = in_dir.dot(normal);
-        let mut new_ray = (in_dir - normal * (2.0 * dot_product)).normalize();
-        let rand_vec = diffuse_ray_direction(seed, normal).normalize();
-        new_ray = new_ray.lerp(rand_vec, roughness);
-        new_ray
+/// Microfacet reflection without the fresnel term, times the cosine of `wi`.
+fn ggx_reflection(ggx: &Ggx, wo: Vec3, wi: Vec3, h: Vec3) -> f32 {
+    ggx.d(h) * ggx.g2(wo, wi) / (4.0 * wo.z)
+}
+
+/// Density of [`reflect_ggx`] returning the direction reflected off `h`.
+fn ggx_reflection_pdf(ggx: &Ggx, wo: Vec3, h: Vec3) -> f32 {
+    let cos_h = wo.dot(h);
+    if cos_h <= 0.0 {
+        return 0.0;
     }
+    ggx.visible_normal_pdf(wo, h) / (4.0 * cos_h)
+}
 
-    fn can_refract(curr_ior: f32, next_ior: f32, curr_sintheta: f32) -> bool {
-        let sintheta2 = curr_ior / next_ior * curr_sintheta;
-        sintheta2 < 1.0
+/// Beer-Lambert coefficient that leaves `color` of the light after `distance`.
+fn absorption_coefficient(color: Vec3, distance: f32) -> Vec3 {
+    if distance <= 0.0 {
+        return Vec3::ZERO;
     }
+    //fully black would need an infinite coefficient
+    let color = color.clamp(Vec3::splat(1e-6), Vec3::ONE);
+    -Vec3::new(color.x.ln(), color.y.ln(), color.z.ln()) / distance
+}
 
-    fn schlick_reflectance(ior1: f32, ior2: f32, cos_theta: f32) -> f32 {
-        let r0 = (ior1 - ior2) / (ior1 + ior2);
-        let r0_squared = r0 * r0;
-        r0_squared + (1.0 - r0_squared) * (1.0 - cos_theta).powi(5)
+fn luminance(color: Vec3) -> f32 {
+    color.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+/// Share of the light each lobe of a [`PrincipledSurface`] gets, doubling as the probability
+/// of sampling it. The layers together never reflect more than arrives: clearcoat on top, then
+/// metal, glass and finally a specular layer over diffuse.
+struct Lobes {
+    clearcoat: f32,
+    metal: f32,
+    glass: f32,
+    /// what is left for the opaque dielectric, split between specular and diffuse
+    dielectric: f32,
+    specular_probability: f32,
+    /// share of the diffuse part going below the surface
+    subsurface_share: f32,
+}
+
+impl Lobes {
+    fn specular(&self) -> f32 {
+        self.dielectric * self.specular_probability
+    }
+
+    fn diffuse(&self) -> f32 {
+        self.dielectric * (1.0 - self.specular_probability) * (1.0 - self.subsurface_share)
     }
 
-    fn reflect_specular(&self, curr_color: Vec3, in_ray: Ray, normal: Vec3, t: f32, seed: &mut u32) -> MaterialReturn {
-        let new_ray = Self::reflect(in_ray.orientation, normal, self.specular_roughness, seed);
+    fn subsurface(&self) -> f32 {
+        self.dielectric * (1.0 - self.specular_probability) * self.subsurface_share
+    }
+}
 
-        //color doesn't change
-        MaterialReturn {
-            ray_return_state: RayReturnState::Ray,
-            new_ray: Ray {
-                pos: in_ray.pos + in_ray.orientation * t,
-                orientation: new_ray,
-            },
-            next_color: curr_color,
+/// [`PrincipledMaterial`] with its textures looked up at the hit point.
+pub struct PrincipledSurface {
+    base_color: Vec3,
+    emission: Vec3,
+    metallic: f32,
+    roughness: f32,
+    /// dielectric reflectance at normal incidence
+    specular_color: Vec3,
+    transmission: f32,
+    ior: f32,
+    clearcoat: f32,
+    clearcoat_roughness: f32,
+    sheen_color: Vec3,
+    absorption: Vec3,
+    subsurface: f32,
+}
+
+impl PrincipledSurface {
+    pub fn new(material: &PrincipledMaterial, uv: (f32, f32), objects: &ObjectInfo) -> Self {
+        let base_color = material.base_color
+            * srgb_to_linear(sample_texture(material.base_color_texture, uv, objects).truncate());
+        let emission = material.emission
+            * material.emission_strength
+            * srgb_to_linear(sample_texture(material.emission_texture, uv, objects).truncate());
+        let metallic_roughness = sample_texture(material.metallic_roughness_texture, uv, objects);
+
+        //hue of the base color without its brightness, for the tint parameters
+        let base_luminance = luminance(base_color);
+        let tint = if base_luminance > 0.0 {
+            base_color / base_luminance
+        } else {
+            Vec3::ONE
+        };
+
+        PrincipledSurface {
+            base_color,
+            emission,
+            metallic: material.metallic * metallic_roughness.z,
+            roughness: material.roughness * metallic_roughness.y,
+            specular_color: Vec3::ONE.lerp(tint, material.specular_tint) * (0.08 * material.specular),
+            transmission: material.transmission,
+            ior: material.ior,
+            clearcoat: material.clearcoat,
+            clearcoat_roughness: material.clearcoat_roughness,
+            sheen_color: Vec3::ONE.lerp(tint, material.sheen_tint) * material.sheen,
+            absorption: absorption_coefficient(material.absorption_color, material.absorption_distance),
+            subsurface: material.subsurface,
         }
     }
 
-    fn reflect_regular(&self, curr_color: Vec3, in_ray: Ray, normal: Vec3, t: f32, seed: &mut u32) -> MaterialReturn {
-        let new_ray = Self::reflect(in_ray.orientation, normal, self.roughness, seed);
+    /// Makes the ior relative to the medium on the outside instead of air, and lets it
+    /// depend on the wavelength.
+    pub fn relative_ior(mut self, ior: f32, outside_ior: f32) -> Self {
+        self.ior = ior / outside_ior;
+        self
+    }
 
-        MaterialReturn {
-            ray_return_state: RayReturnState::Ray,
-            new_ray: Ray {
-                pos: in_ray.pos + in_ray.orientation * t,
-                orientation: new_ray,
-            },
-            next_color: curr_color * self.color,
+    fn lobes(&self, cos_o: f32) -> Lobes {
+        let clearcoat = self.clearcoat * fresnel_dielectric(cos_o, 1.5);
+        let metal = (1.0 - clearcoat) * self.metallic;
+        let glass = (1.0 - clearcoat - metal) * self.transmission;
+        let fresnel = fresnel_schlick(self.specular_color, cos_o);
+
+        Lobes {
+            clearcoat,
+            metal,
+            glass,
+            dielectric: 1.0 - clearcoat - metal - glass,
+            specular_probability: (fresnel.x + fresnel.y + fresnel.z) / 3.0,
+            subsurface_share: self.subsurface,
         }
     }
 
-    fn refract(&self, curr_color: Vec3, in_ray: Ray, normal: Vec3, t: f32, seed: &mut u32) -> MaterialReturn {
-        let front_face = in_ray.orientation.dot(normal) < 0.0;
-        //normal vector on the incoming side of the surface
-        let normal_incoming = if front_face {
-            normal
+    /// Frame on the side of `wo` and the ior on the far side over the one on the near side.
+    fn local_frame(&self, wo: Vec3, normal: Vec3) -> (Frame, f32) {
+        if wo.dot(normal) >= 0.0 {
+            (Frame::new(normal), self.ior)
         } else {
-            -normal
-        };
+            (Frame::new(-normal), 1.0 / self.ior)
+        }
+    }
 
-        let refraction_ratio = if front_face { 1.0 / self.ior } else { self.ior };
+    /// Bsdf times cosine and the sampling density together, in local space, as most of the
+    /// work is shared.
+    fn eval_pdf(&self, wo: Vec3, wi: Vec3, eta: f32) -> (Vec3, f32) {
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return (Vec3::ZERO, 0.0);
+        }
+        let lobes = self.lobes(wo.z);
+        let ggx = Ggx::from_roughness(self.roughness);
+
+        if wi.z < 0.0 {
+            //subsurface light goes in diffusely, its color comes from the walk inside
+            let subsurface = lobes.subsurface() * -wi.z / PI;
+            let mut value = Vec3::splat(subsurface);
+            let mut pdf = subsurface;
+
+            //glass lets light through off a microfacet between the two directions
+            let mut h = -(wo + wi * eta).normalize_or_zero();
+            if h.z < 0.0 {
+                h = -h;
+            }
+            let cos_o = wo.dot(h);
+            let cos_i = wi.dot(h);
+            if cos_o > 0.0 && cos_i < 0.0 {
+                let denominator = cos_o + eta * cos_i;
+                //change from microfacet normals to refracted directions
+                let jacobian = eta * eta * -cos_i / (denominator * denominator);
+                let transmitted = lobes.glass * (1.0 - fresnel_dielectric(cos_o, eta));
+
+                value += self.base_color
+                    * (transmitted * ggx.d(h) * ggx.g2(wo, wi) * cos_o * jacobian / wo.z);
+                pdf += transmitted * ggx.visible_normal_pdf(wo, h) * jacobian;
+            }
+            return (value, pdf);
+        }
 
-        let next_ray_perfect = in_ray.orientation.refract(normal_incoming, refraction_ratio).normalize();
+        let h = (wo + wi).normalize();
+        let cos_h = wo.dot(h);
+        let coat = Ggx::from_roughness(self.clearcoat_roughness);
+        let reflection = ggx_reflection(&ggx, wo, wi, h);
+        let glass_fresnel = fresnel_dielectric(cos_h, eta);
 
-        let next_ray_diffuse = diffuse_ray_direction(seed, normal).normalize();
-        let next_ray = next_ray_perfect.lerp(next_ray_diffuse, self.roughness);
+        //sheen grows towards grazing angles between light and view
+        let cos_d = wi.dot(h).clamp(0.0, 1.0);
+        let diffuse = (self.base_color / PI + self.sheen_color * (1.0 - cos_d).powi(5)) * wi.z;
 
-        MaterialReturn {
-            ray_return_state: RayReturnState::Ray,
-            new_ray: Ray {
-                pos: in_ray.pos + in_ray.orientation * t,
-                orientation: next_ray.normalize(),
-            },
-            next_color: curr_color
-        }
+        let value = Vec3::splat(lobes.clearcoat * ggx_reflection(&coat, wo, wi, h))
+            + fresnel_schlick(self.base_color, cos_h) * (lobes.metal * reflection)
+            + Vec3::splat(lobes.glass * glass_fresnel * reflection)
+            + fresnel_schlick(self.specular_color, cos_h) * (lobes.dielectric * reflection)
+            + diffuse * lobes.diffuse();
+
+        let reflection_pdf = ggx_reflection_pdf(&ggx, wo, h);
+        let pdf = lobes.clearcoat * ggx_reflection_pdf(&coat, wo, h)
+            + (lobes.metal + lobes.glass * glass_fresnel + lobes.specular()) * reflection_pdf
+            + lobes.diffuse() * wi.z / PI;
+        (value, pdf)
     }
 }
 
-impl Material for GenericMaterial {
-    fn bxdf(
+impl Material for PrincipledSurface {
+    fn emission(&self, _wo: Vec3, _normal: Vec3, _uv: (f32, f32), _objects: &ObjectInfo) -> Vec3 {
+        self.emission
+    }
+
+    //picks one lobe, then weighs the direction by all of them so overlapping lobes don't add noise
+    fn sample(
         &self,
-        curr_color: Vec3,
-        in_ray: Ray,
+        wo: Vec3,
         normal: Vec3,
-        uv: (f32, f32),
-        t: f32,
+        _uv: (f32, f32),
         seed: &mut u32,
-    ) -> MaterialReturn {
-        let cos_theta = in_ray.orientation.dot(normal);
-        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
-
-        if self.specular > 0.0 {
-            let rand_specular = rand_float(seed, (0.0, 1.0));
-            if self.specular > rand_specular {
-                return self.reflect_specular(curr_color, in_ray, normal, t, seed);
-            }
+        _objects: &ObjectInfo,
+    ) -> BsdfSample {
+        let (frame, eta) = self.local_frame(wo, normal);
+        let wo_local = frame.to_local(wo);
+        if wo_local.z <= 0.0 {
+            return BsdfSample::ABSORBED;
         }
-
-        if self.ior > 0.0 {
-            let ior_parts = if in_ray.orientation.dot(normal) < 0.0 {
-                (1.0, self.ior)
+        let lobes = self.lobes(wo_local.z);
+        let ggx = Ggx::from_roughness(self.roughness);
+
+        let lobe = rand_float(seed, (0.0, 1.0));
+        let wi = if lobe < lobes.clearcoat {
+            reflect_ggx(wo_local, &Ggx::from_roughness(self.clearcoat_roughness), seed)
+        } else if lobe < lobes.clearcoat + lobes.metal {
+            reflect_ggx(wo_local, &ggx, seed)
+        } else if lobe < lobes.clearcoat + lobes.metal + lobes.glass {
+            //every microfacet either reflects or refracts depending on its fresnel term
+            let h = ggx.sample_visible_normal(wo_local, seed);
+            let cos_h = wo_local.dot(h);
+            if rand_float(seed, (0.0, 1.0)) < fresnel_dielectric(cos_h, eta) {
+                h * (2.0 * cos_h) - wo_local
             } else {
-                (self.ior, 1.0)
-            };
-            let reflectance = Self::schlick_reflectance(ior_parts.0, ior_parts.1, cos_theta.abs()).min(1.0);
-            let rand_refract = rand_float(seed, (0.0, 1.0));
-            if rand_refract > reflectance && Self::can_refract(ior_parts.0, ior_parts.1, sin_theta) {
-                return self.refract(curr_color, in_ray, normal, t, seed);
-            } else {
-                return self.reflect_specular(curr_color, in_ray, normal, t, seed);
+                (-wo_local).refract(h, 1.0 / eta)
             }
-        }
+        } else if lobe < 1.0 - lobes.diffuse() - lobes.subsurface() {
+            reflect_ggx(wo_local, &ggx, seed)
+        } else if lobe < 1.0 - lobes.subsurface() {
+            sample_cosine_hemisphere(Vec3::Z, seed)
+        } else {
+            -sample_cosine_hemisphere(Vec3::Z, seed)
+        };
+        let picked = if wi.z < 0.0 {
+            Lobe::Transmission
+        } else if lobe < 1.0 - lobes.diffuse() - lobes.subsurface() {
+            Lobe::Specular
+        } else {
+            Lobe::Diffuse
+        };
 
-        self.reflect_regular(curr_color, in_ray, normal, t, seed)
+        let (value, pdf) = self.eval_pdf(wo_local, wi, eta);
+        if pdf <= 0.0 {
+            return BsdfSample::ABSORBED;
+        }
+        BsdfSample {
+            direction: frame.to_world(wi).normalize(),
+            lobe: picked,
+            weight: value / pdf,
+            pdf,
+        }
     }
 
-    fn backface_culling(&self) -> bool {
-        false
+    fn eval(&self, wo: Vec3, wi: Vec3, normal: Vec3, _uv: (f32, f32), _objects: &ObjectInfo) -> Vec3 {
+        let (frame, eta) = self.local_frame(wo, normal);
+        self.eval_pdf(frame.to_local(wo), frame.to_local(wi), eta).0
     }
-}
 
-pub struct DiffuseMaterial {
-    pub color: Vec3,
-}
+    fn pdf(&self, wo: Vec3, wi: Vec3, normal: Vec3, _uv: (f32, f32), _objects: &ObjectInfo) -> f32 {
+        let (frame, eta) = self.local_frame(wo, normal);
+        self.eval_pdf(frame.to_local(wo), frame.to_local(wi), eta).1
+    }
 
-impl DiffuseMaterial {
-    pub const fn new(color: Vec3) -> Self {
-        DiffuseMaterial { color }
+    fn absorption(&self) -> Vec3 {
+        self.absorption
     }
 }
 
-impl DiffuseMaterial {
-    fn get_color(
-        &self,
-        next_ray_color: Vec3,
-        _normal: Vec3,
-        _uv: (f32, f32),
-        _ray_dir: Vec3,
-    ) -> Vec3 {
-        next_ray_color * self.color
+impl Material for PrincipledMaterial {
+    fn backface_culling(&self) -> bool {
+        false
     }
 
-    fn get_next_ray_dir(&self, seed: &mut u32, ray: Ray, normal: Vec3) -> RayReturn {
-        let direction = diffuse_ray_direction(seed, normal);
+    //emitters are what shadow rays are aimed at, they shouldn't block them, and volumes
+    //only dim them
+    fn casts_shadow(&self) -> bool {
+        self.emission_strength <= 0.0 && self.model != MaterialModel::Volume
+    }
 
-        RayReturn {
-            state: RayReturnState::Ray,
-            direction,
+    fn shading_normal(
+        &self,
+        frame: &TangentFrame,
+        uv: (f32, f32),
+        scene_info: &SceneInfo,
+        objects: &ObjectInfo,
+    ) -> Vec3 {
+        if !self.normal_texture.is_none() {
+            apply_normal_map(frame, self.normal_texture, uv, objects)
+        } else {
+            apply_bump_map(frame, self.bump_texture, self.bump_strength, uv, scene_info, objects)
         }
     }
-}
-
-pub struct MetalMaterial {
-    pub color: Vec3,
-    roughness: f32,
-}
 
-impl MetalMaterial {
-    pub const fn new(color: Vec3, roughness: f32) -> Self {
-        MetalMaterial { color, roughness }
+    fn emission(&self, wo: Vec3, normal: Vec3, uv: (f32, f32), objects: &ObjectInfo) -> Vec3 {
+        PrincipledSurface::new(self, uv, objects).emission(wo, normal, uv, objects)
     }
-}
 
-impl MetalMaterial {
-    fn get_color(
+    fn sample(
         &self,
-        next_ray_color: Vec3,
-        _normal: Vec3,
-        _uv: (f32, f32),
-        _ray_dir: Vec3,
-    ) -> Vec3 {
-        next_ray_color * self.color
+        wo: Vec3,
+        normal: Vec3,
+        uv: (f32, f32),
+        seed: &mut u32,
+        objects: &ObjectInfo,
+    ) -> BsdfSample {
+        PrincipledSurface::new(self, uv, objects).sample(wo, normal, uv, seed, objects)
     }
 
-    fn get_next_ray_dir(&self, seed: &mut u32, ray: Ray, normal: Vec3) -> RayReturn {
-        let old_ray = ray.orientation;
-        let mut new_ray = old_ray.reflect(normal).normalize();
-        let rand_vec = diffuse_ray_direction(seed, normal).normalize();
-        new_ray = new_ray.lerp(rand_vec, self.roughness);
+    fn eval(&self, wo: Vec3, wi: Vec3, normal: Vec3, uv: (f32, f32), objects: &ObjectInfo) -> Vec3 {
+        PrincipledSurface::new(self, uv, objects).eval(wo, wi, normal, uv, objects)
+    }
 
-        RayReturn {
-            state: RayReturnState::Ray,
-            direction: new_ray,
-        }
+    fn pdf(&self, wo: Vec3, wi: Vec3, normal: Vec3, uv: (f32, f32), objects: &ObjectInfo) -> f32 {
+        PrincipledSurface::new(self, uv, objects).pdf(wo, wi, normal, uv, objects)
     }
 }
 
 pub struct NormalMaterial {}
 
 impl NormalMaterial {
-    fn get_next_ray_dir(&self, seed: &mut u32, ray: Ray, normal: Vec3) -> RayReturn {
-        RayReturn {
-            state: RayReturnState::Ray,
-            direction: diffuse_ray_direction(seed, normal),
+    fn get_color(&self, normal: Vec3) -> Vec3 {
+        if normal.dot(Vec3::new(0.0, 1.0, 0.0)).abs() > 0.9 { //top/bottom
+            Vec3::new(1.0, 1.0, 1.0) //white
+        } else if normal.dot(Vec3::new(-1.0, 0.0, 0.0)) > 0.9 { //right
+            Vec3::new(0.1, 1.0, 0.1) //green
+        } else if normal.dot(Vec3::new(1.0, 0.0, 0.0)) > 0.9 { //left
+            Vec3::new(0.1, 0.1, 1.0) //red
+        } else if normal.dot(Vec3::new(0.0, 0.0, -1.0)) > 0.9 { //front
+            Vec3::new(1.0, 0.1, 0.1) //blue
+        } else if normal.dot(Vec3::new(0.0, 0.0, 1.0)) > 0.9 { //back
+            Vec3::ZERO //black
+        } else {
+            Vec3::ZERO //default black
         }
     }
-
-    fn get_color(
-        &self,
-        next_ray_color: Vec3,
-        normal: Vec3,
-        _uv: (f32, f32),
-        _ray_dir: Vec3,
-    ) -> Vec3 {
-        let color = {
-            if normal.dot(Vec3::new(0.0, 1.0, 0.0)).abs() > 0.9 { //top/bottom
-                Vec3::new(1.0, 1.0, 1.0) //white
-            } else if normal.dot(Vec3::new(-1.0, 0.0, 0.0)) > 0.9 { //right
-                Vec3::new(0.1, 1.0, 0.1) //green
-            } else if normal.dot(Vec3::new(1.0, 0.0, 0.0)) > 0.9 { //left
-                Vec3::new(0.1, 0.1, 1.0) //red
-            } else if normal.dot(Vec3::new(0.0, 0.0, -1.0)) > 0.9 { //front
-                Vec3::new(1.0, 0.1, 0.1) //blue
-            } else if normal.dot(Vec3::new(0.0, 0.0, 1.0)) > 0.9 { //back
-                Vec3::ZERO //black
-            } else {
-                Vec3::ZERO //default black
-            }
-        };
-        next_ray_color * color
-    }
 }
 
+//lambertian, colored by the direction the surface faces
 impl Material for NormalMaterial {
-    fn bxdf(
+    fn sample(
         &self,
-        curr_color: Vec3,
-        in_ray: Ray,
+        wo: Vec3,
         normal: Vec3,
-        uv: (f32, f32),
-        t: f32,
+        _uv: (f32, f32),
         seed: &mut u32,
-    ) -> MaterialReturn {
-        let next_ray_return = self.get_next_ray_dir(seed, in_ray, normal);
-        let next_color = self.get_color(curr_color, normal, uv, in_ray.orientation);
-
-        MaterialReturn {
-            ray_return_state: next_ray_return.state,
-            new_ray: Ray {
-                pos: in_ray.pos + in_ray.orientation * t,
-                orientation: next_ray_return.direction,
-            },
-            next_color,
+        _objects: &ObjectInfo,
+    ) -> BsdfSample {
+        let facing_normal = if wo.dot(normal) < 0.0 { -normal } else { normal };
+        let direction = sample_cosine_hemisphere(facing_normal, seed);
+        BsdfSample {
+            direction,
+            lobe: Lobe::Diffuse,
+            weight: self.get_color(normal),
+            pdf: direction.dot(facing_normal).max(0.0) / PI,
         }
     }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, normal: Vec3, uv: (f32, f32), objects: &ObjectInfo) -> Vec3 {
+        self.get_color(normal) * self.pdf(wo, wi, normal, uv, objects)
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, normal: Vec3, _uv: (f32, f32), _objects: &ObjectInfo) -> f32 {
+        let cos_o = wo.dot(normal);
+        let cos_i = wi.dot(normal);
+        if cos_o * cos_i <= 0.0 {
+            return 0.0;
+        }
+        cos_i.abs() / PI
+    }
 }
 
-pub struct BackgroundMaterial {}
+pub struct BackgroundMaterial {
+    pub sun: Sun,
+    pub turbidity: f32,
+    pub intensity: f32,
+    pub ground_albedo: Vec3,
+}
 
 impl BackgroundMaterial {
-    fn get_next_ray_dir(&self, _seed: &mut u32, _ray: Ray, _normal: Vec3) -> RayReturn {
-        RayReturn {
-            state: RayReturnState::Stop,
-            direction: Vec3::default(),
+    pub fn new(scene_info: &SceneInfo) -> Self {
+        Self {
+            sun: Sun::new(scene_info),
+            turbidity: scene_info.sky_turbidity,
+            intensity: scene_info.sky_intensity,
+            ground_albedo: scene_info.ground_albedo,
         }
     }
 
     pub fn get_stop_color(&self, _normal: Vec3, _uv: (f32, f32), ray_dir: Vec3) -> Vec3 {
         let temp = ray_dir.normalize();
 
-        let factor = (temp.y + 0.5).clamp(0.0, 1.0);
-        Vec3::new(1.0, 1.0, 1.0) * (1.0 - factor) + Vec3::new(0.5, 0.7, 1.0) * factor
+        if temp.y >= 0.0 {
+            return sky(temp, self.sun.direction, self.turbidity) * self.intensity;
+        }
+
+        //below the horizon there is an infinite lambertian ground lit by the sun and,
+        //roughly, by a sky as bright as its zenith
+        let sky_irradiance =
+            sky(Vec3::new(0.0, 1.0, 0.0), self.sun.direction, self.turbidity) * (self.intensity * PI);
+        let sun_irradiance = self.sun.irradiance * self.sun.direction.y.max(0.0);
+        self.ground_albedo * (sky_irradiance + sun_irradiance) / PI
     }
 }
 
 pub struct EmmissiveMaterial {
     pub light_color: Vec3,
+    /// multiplies `light_color`
+    pub texture: TextureSlot,
 }
 
 impl EmmissiveMaterial {
     pub const fn new(light_color: Vec3) -> Self {
-        Self { light_color }
+        Self {
+            light_color,
+            texture: TextureSlot::NONE,
+        }
     }
 
-    fn get_next_ray_dir(&self, _seed: &mut u32, _ray: Ray, _normal: Vec3) -> RayReturn {
-        RayReturn {
-            state: RayReturnState::Stop,
-            direction: Vec3::default(),
+    pub const fn textured(light_color: Vec3, texture: TextureSlot) -> Self {
+        Self {
+            light_color,
+            texture,
         }
     }
+}
 
-    fn get_stop_color(&self, normal: Vec3, _uv: (f32, f32), ray_dir: Vec3) -> Vec3 {
-        let ray_reversed = -ray_dir.normalize();
-
-        let dot_product = ray_reversed.dot(normal).abs(); //abs for weird geometries that have gaps
-                                                          //into backface triangles
+//a pure light, it gives off light but never reflects any
+impl Material for EmmissiveMaterial {
+    fn emission(&self, wo: Vec3, normal: Vec3, uv: (f32, f32), objects: &ObjectInfo) -> Vec3 {
+        let dot_product = wo.dot(normal).abs(); //abs for weird geometries that have gaps
+                                                //into backface triangles
 
-        self.light_color * dot_product.sqrt()
+        let texture = srgb_to_linear(sample_texture(self.texture, uv, objects).truncate());
+        self.light_color * texture * dot_product.sqrt()
     }
-}
 
-impl Material for EmmissiveMaterial {
-    fn bxdf(
+    fn sample(
         &self,
-        curr_color: Vec3,
-        in_ray: Ray,
-        normal: Vec3,
-        uv: (f32, f32),
-        t: f32,
-        seed: &mut u32,
-    ) -> MaterialReturn {
-        let next_ray_return = self.get_next_ray_dir(seed, in_ray, normal);
-        let next_color = self.get_stop_color(normal, uv, in_ray.orientation);
-        MaterialReturn {
-            ray_return_state: next_ray_return.state,
-            new_ray: Ray {
-                pos: in_ray.pos + in_ray.orientation * t,
-                orientation: next_ray_return.direction,
-            },
-            next_color: curr_color * next_color,
-        }
+        _wo: Vec3,
+        _normal: Vec3,
+        _uv: (f32, f32),
+        _seed: &mut u32,
+        _objects: &ObjectInfo,
+    ) -> BsdfSample {
+        BsdfSample::ABSORBED
     }
-}
 
-pub struct RefractiveMaterial {
-    _color: Vec3,
-    ior: f32,
-}
-
-impl RefractiveMaterial {
-    pub const fn new(color: Vec3, ior: f32) -> Self {
-        Self { _color: color, ior }
+    fn eval(&self, _wo: Vec3, _wi: Vec3, _normal: Vec3, _uv: (f32, f32), _objects: &ObjectInfo) -> Vec3 {
+        Vec3::ZERO
     }
 
-    pub fn reflectance(&self, normal: Vec3, ray: Ray) -> f32 {
-        //using schlick's approximation
-        let mut r0 = (1.0 - self.ior) / (1.0 + self.ior);
-        r0 *= r0;
-        let cos_theta = normal.dot(-ray.orientation);
-        r0 + (1.0 - r0) * (1.0 - cos_theta).powi(5)
+    fn pdf(&self, _wo: Vec3, _wi: Vec3, _normal: Vec3, _uv: (f32, f32), _objects: &ObjectInfo) -> f32 {
+        0.0
     }
 
-    pub fn reflect(&self, normal: Vec3, ray: Ray) -> Vec3 {
-        let old_ray = ray.orientation;
-        old_ray.reflect(normal).normalize()
+    //lights are what shadow rays are aimed at, they shouldn't block them
+    fn casts_shadow(&self) -> bool {
+        false
     }
+}
 
-    pub fn refract(&self, normal: Vec3, ray: Ray) -> Vec3 {
-        let front_face = ray.orientation.dot(normal) < 0.0;
-        // let in_ray = ray.orientation.normalize();
-        //
-        // let refraction_ratio = if front_face { 1.0 / self.ior } else { self.ior };
-        // let cos_theta = normal.dot(-in_ray);
-        // let r1 = (ray.orientation + normal * cos_theta) * refraction_ratio;
-        // let r2 = -normal * (1.0 - r1.length_squared()).sqrt();
-        // r1 + r2
-
-        let unit_in = ray.orientation.normalize();
-        let cos_theta = (-unit_in).dot(normal).min(1.0);
-
-        let refraction_ratio = if front_face { 1.0 / self.ior } else { self.ior };
-
-        // Check for total internal reflection (you said you already handle it)
-        let r_out_perp = (unit_in + normal * cos_theta) * refraction_ratio;
-        let r_out_parallel = -normal * (1.0 - r_out_perp.length_squared()).abs().sqrt();
+/// Where a subsurface walk comes back out, lambertian towards `normal` with the color already
+/// picked up inside.
+pub struct SubsurfaceExit {}
 
-        r_out_perp + r_out_parallel
-    }
-
-    fn get_color(
+impl Material for SubsurfaceExit {
+    fn sample(
         &self,
-        next_ray_color: Vec3,
-        _normal: Vec3,
+        _wo: Vec3,
+        normal: Vec3,
         _uv: (f32, f32),
-        _ray_dir: Vec3,
-    ) -> Vec3 {
-        next_ray_color
+        seed: &mut u32,
+        _objects: &ObjectInfo,
+    ) -> BsdfSample {
+        let direction = sample_cosine_hemisphere(normal, seed);
+        BsdfSample {
+            direction,
+            lobe: Lobe::Transmission,
+            weight: Vec3::ONE,
+            pdf: direction.dot(normal).max(0.0) / PI,
+        }
     }
 
-    fn get_next_ray_dir(&self, seed: &mut u32, ray: Ray, normal: Vec3) -> RayReturn {
-        let front_face = ray.orientation.dot(normal) < 0.0;
-
-        let refraction_ratio = if front_face { 1.0 / self.ior } else { self.ior };
-        let cos_theta = normal.dot(-ray.orientation);
-        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
-        let reflectance = self.reflectance(normal, ray);
-        if refraction_ratio * sin_theta > 1.0 || reflectance > rand_float(seed, (0.0, 1.0)) {
-            RayReturn {
-                state: RayReturnState::Ray,
-                direction: self.reflect(normal, ray),
-            }
-        } else {
-            RayReturn {
-                state: RayReturnState::Ray,
-                direction: self.refract(normal, ray),
-            }
-        }
+    fn eval(&self, _wo: Vec3, wi: Vec3, normal: Vec3, _uv: (f32, f32), _objects: &ObjectInfo) -> Vec3 {
+        Vec3::splat(wi.dot(normal).max(0.0) / PI)
     }
-}
 
-impl Material for RefractiveMaterial {
-    fn bxdf(
-        &self,
-        curr_color: Vec3,
-        in_ray: Ray,
-        normal: Vec3,
-        uv: (f32, f32),
-        t: f32,
-        seed: &mut u32,
-    ) -> MaterialReturn {
-        let next_ray_return = self.get_next_ray_dir(seed, in_ray, normal);
-        let next_color = self.get_color(curr_color, normal, uv, in_ray.orientation);
-
-        MaterialReturn {
-            ray_return_state: next_ray_return.state,
-            new_ray: Ray {
-                pos: in_ray.pos + in_ray.orientation * t,
-                orientation: next_ray_return.direction,
-            },
-            next_color,
-        }
+    fn pdf(&self, _wo: Vec3, wi: Vec3, normal: Vec3, _uv: (f32, f32), _objects: &ObjectInfo) -> f32 {
+        wi.dot(normal).max(0.0) / PI
     }
 }
 
@@ -492,39 +631,30 @@ impl UVMaterial {
     pub fn new() -> Self {
         Self {}
     }
+}
 
-    fn get_stop_color(&self, _normal: Vec3, uv: (f32, f32), _ray_dir: Vec3) -> Vec3 {
+//shows the texture coordinates as if they were light, the path ends here
+impl Material for UVMaterial {
+    fn emission(&self, _wo: Vec3, _normal: Vec3, uv: (f32, f32), _objects: &ObjectInfo) -> Vec3 {
         Vec3::new(uv.0, uv.1, 0.0)
     }
 
-    fn get_next_ray_dir(&self, _seed: &mut u32, _ray: Ray, _normal: Vec3) -> RayReturn {
-        RayReturn {
-            state: RayReturnState::Stop,
-            direction: Vec3::default(),
-        }
+    fn sample(
+        &self,
+        _wo: Vec3,
+        _normal: Vec3,
+        _uv: (f32, f32),
+        _seed: &mut u32,
+        _objects: &ObjectInfo,
+    ) -> BsdfSample {
+        BsdfSample::ABSORBED
     }
-}
 
-impl Material for UVMaterial {
-    fn bxdf(
-        &self,
-        curr_color: Vec3,
-        in_ray: Ray,
-        normal: Vec3,
-        uv: (f32, f32),
-        t: f32,
-        seed: &mut u32,
-    ) -> MaterialReturn {
-        let next_ray_return = self.get_next_ray_dir(seed, in_ray, normal);
-        let next_color = self.get_stop_color(normal, uv, in_ray.orientation);
-
-        MaterialReturn {
-            ray_return_state: next_ray_return.state,
-            new_ray: Ray {
-                pos: in_ray.pos + in_ray.orientation * t,
-                orientation: next_ray_return.direction,
-            },
-            next_color: curr_color * next_color,
-        }
+    fn eval(&self, _wo: Vec3, _wi: Vec3, _normal: Vec3, _uv: (f32, f32), _objects: &ObjectInfo) -> Vec3 {
+        Vec3::ZERO
+    }
+
+    fn pdf(&self, _wo: Vec3, _wi: Vec3, _normal: Vec3, _uv: (f32, f32), _objects: &ObjectInfo) -> f32 {
+        0.0
     }
 }
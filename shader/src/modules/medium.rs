@@ -0,0 +1,253 @@
+use core::f32::consts::PI;
+
+use super::material::{BsdfSample, Lobe, Material};
+use super::microfacet::Frame;
+use super::rand_float;
+use super::ObjectInfo;
+use shared::glam::Vec3;
+use shared::PrincipledMaterial;
+use shared::NO_DENSITY_GRID;
+#[allow(unused_imports)] //actually used for .sqrt because we don't allow std
+use spirv_std::num_traits::Float;
+
+const MAX_MEDIA: usize = 4;
+
+/// Inside of a transmissive or volume instance a path has entered.
+#[derive(Clone, Copy)]
+pub struct Medium {
+    pub instance_id: u32,
+    pub priority: u32,
+    pub ior: f32,
+    /// per unit of distance, like `scattering`
+    pub absorption: Vec3,
+    pub scattering: Vec3,
+    pub anisotropy: f32,
+    /// density grid scaling the coefficients, or [`NO_DENSITY_GRID`]
+    pub grid: u32,
+    /// under a subsurface material, paths walk through it
+    pub subsurface: bool,
+}
+
+impl Medium {
+    //nothing was entered yet
+    pub const AIR: Self = Medium {
+        instance_id: u32::MAX,
+        priority: 0,
+        ior: 1.0,
+        absorption: Vec3::ZERO,
+        scattering: Vec3::ZERO,
+        anisotropy: 0.0,
+        grid: NO_DENSITY_GRID,
+        subsurface: false,
+    };
+
+    /// Inside of `material`, `absorption` coming from its tint on top of what the
+    /// scattering medium absorbs.
+    pub fn new(instance_id: u32, material: &PrincipledMaterial, ior: f32, absorption: Vec3) -> Self {
+        if material.subsurface > 0.0 {
+            let albedo = random_walk_albedo(material.subsurface_color);
+            let extinction = Vec3::ONE / material.subsurface_radius.max(Vec3::splat(1e-4));
+            return Medium {
+                instance_id,
+                priority: material.priority,
+                ior,
+                absorption: absorption + (Vec3::ONE - albedo) * extinction,
+                scattering: albedo * extinction,
+                anisotropy: material.anisotropy,
+                grid: NO_DENSITY_GRID,
+                subsurface: true,
+            };
+        }
+
+        let albedo = material.scattering_albedo.clamp(Vec3::ZERO, Vec3::ONE);
+        Medium {
+            instance_id,
+            priority: material.priority,
+            ior,
+            absorption: absorption + (Vec3::ONE - albedo) * material.density,
+            scattering: albedo * material.density,
+            anisotropy: material.anisotropy,
+            grid: material.density_grid,
+            subsurface: false,
+        }
+    }
+
+    pub fn extinction(&self) -> Vec3 {
+        self.absorption + self.scattering
+    }
+}
+
+/// Single scattering albedo that makes a random walk through a thick medium come back out
+/// with `color`.
+/// Based on: Chiang et al., "Practical and Controllable Subsurface Scattering for Production Path Tracing"
+fn random_walk_albedo(color: Vec3) -> Vec3 {
+    let color = color.clamp(Vec3::ZERO, Vec3::splat(0.999));
+    let root = (Vec3::splat(9.59217) + color * 41.6808 + color * color * 17.7126)
+        .max(Vec3::ZERO);
+    let root = Vec3::new(root.x.sqrt(), root.y.sqrt(), root.z.sqrt());
+    let s = Vec3::splat(4.09712) + color * 4.20863 - root;
+    Vec3::ONE - s * s
+}
+
+/// Phase function of a medium, plays the part of the bsdf at points inside it. Surface
+/// normals mean nothing there and are ignored.
+/// Based on: Henyey and Greenstein, "Diffuse radiation in the galaxy"
+pub struct HenyeyGreenstein {
+    pub g: f32,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f32) -> Self {
+        //fully forward or backward would be a delta, which the lights can't be sampled for
+        HenyeyGreenstein {
+            g: g.clamp(-0.99, 0.99),
+        }
+    }
+
+    /// Density over directions, `cos_theta` being between the old and the new direction of
+    /// travel.
+    fn phase(&self, cos_theta: f32) -> f32 {
+        let g = self.g;
+        let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * PI * denominator * denominator.max(0.0).sqrt())
+    }
+}
+
+impl Material for HenyeyGreenstein {
+    fn sample(
+        &self,
+        wo: Vec3,
+        _normal: Vec3,
+        _uv: (f32, f32),
+        seed: &mut u32,
+        _objects: &ObjectInfo,
+    ) -> BsdfSample {
+        let g = self.g;
+        let u = rand_float(seed, (0.0, 1.0));
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = rand_float(seed, (0.0, 2.0 * PI));
+        let local = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+
+        //the phase function is sampled exactly, so it cancels out of the weight
+        BsdfSample {
+            direction: Frame::new(-wo).to_world(local).normalize(),
+            lobe: Lobe::Volume,
+            weight: Vec3::ONE,
+            pdf: self.phase(cos_theta),
+        }
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, _normal: Vec3, _uv: (f32, f32), _objects: &ObjectInfo) -> Vec3 {
+        Vec3::splat(self.phase(-wo.dot(wi)))
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, _normal: Vec3, _uv: (f32, f32), _objects: &ObjectInfo) -> f32 {
+        self.phase(-wo.dot(wi))
+    }
+}
+
+/// Every medium a path is inside of, so overlapping and nested dielectrics refract and absorb
+/// with the right one.
+/// Based on: Schmidt and Budge, "Simple Nested Dielectrics in Ray Traced Images"
+#[derive(Clone, Copy)]
+pub struct MediumStack {
+    media: [Medium; MAX_MEDIA],
+    len: usize,
+}
+
+impl MediumStack {
+    pub const AIR: Self = MediumStack {
+        media: [Medium::AIR; MAX_MEDIA],
+        len: 0,
+    };
+
+    /// The one the path is actually travelling through.
+    pub fn current(&self) -> Medium {
+        self.outside(u32::MAX)
+    }
+
+    /// What is on the other side of a surface of `instance_id`, the highest priority medium
+    /// apart from it, ties going to the one entered last.
+    pub fn outside(&self, instance_id: u32) -> Medium {
+        let mut medium = Medium::AIR;
+        for i in 0..self.len {
+            let entry = self.media[i];
+            if entry.instance_id != instance_id && entry.priority >= medium.priority {
+                medium = entry;
+            }
+        }
+        medium
+    }
+
+    //paths that end up inside too many things at once just don't remember the last one
+    pub fn enter(&mut self, medium: Medium) {
+        if self.len < MAX_MEDIA {
+            self.media[self.len] = medium;
+            self.len += 1;
+        }
+    }
+
+    pub fn leave(&mut self, instance_id: u32) {
+        let mut i = 0;
+        while i < self.len {
+            if self.media[i].instance_id == instance_id {
+                let mut j = i;
+                while j + 1 < self.len {
+                    self.media[j] = self.media[j + 1];
+                    j += 1;
+                }
+                self.len -= 1;
+                return;
+            }
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //diffuse reflectance of a semi-infinite slab below z = 0 with unit extinction, walked the
+    //same way the renderer does, light entering cosine weighted and scattering isotropically
+    fn slab_reflectance(albedo: f32, walks: u32, seed: &mut u32) -> f32 {
+        let mut reflected = 0.0;
+        for _ in 0..walks {
+            let mut cos_theta = -rand_float(seed, (0.0, 1.0)).sqrt();
+            let mut z = 0.0;
+            let mut weight = 1.0;
+            while weight > 1e-4 {
+                z -= (1.0 - rand_float(seed, (0.0, 1.0))).max(1e-6).ln() * cos_theta;
+                if z > 0.0 {
+                    reflected += weight;
+                    break;
+                }
+                weight *= albedo;
+                cos_theta = rand_float(seed, (-1.0, 1.0));
+            }
+        }
+        reflected / walks as f32
+    }
+
+    #[test]
+    fn random_walk_reflects_the_subsurface_color() {
+        let mut seed = 0x2545_f491;
+        for color in [0.2, 0.5, 0.8, 0.95] {
+            let albedo = random_walk_albedo(Vec3::splat(color)).x;
+            let reflectance = slab_reflectance(albedo, 50_000, &mut seed);
+            assert!(
+                (reflectance - color).abs() < 0.02,
+                "color {}: albedo {} reflects {}",
+                color,
+                albedo,
+                reflectance
+            );
+        }
+    }
+}
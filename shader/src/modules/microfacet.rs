@@ -0,0 +1,266 @@
+use core::f32::consts::PI;
+
+use super::orthonormal_basis;
+use super::rand_float;
+use shared::glam::Vec3;
+#[allow(unused_imports)] //actually used for .sqrt because we don't allow std
+use spirv_std::num_traits::Float;
+
+//perfectly smooth surfaces make the distribution a dirac delta, which breaks the math
+const MIN_ALPHA: f32 = 1e-3;
+
+/// Orthonormal basis around a normal, microfacet math happens in the space where it is +z.
+pub struct Frame {
+    pub x: Vec3,
+    pub y: Vec3,
+    pub z: Vec3,
+}
+
+impl Frame {
+    pub fn new(normal: Vec3) -> Self {
+        let (x, y) = orthonormal_basis(normal);
+        Frame { x, y, z: normal }
+    }
+
+    pub fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(self.x), v.dot(self.y), v.dot(self.z))
+    }
+
+    pub fn to_world(&self, v: Vec3) -> Vec3 {
+        self.x * v.x + self.y * v.y + self.z * v.z
+    }
+}
+
+/// Isotropic GGX (Trowbridge-Reitz) distribution with Smith height-correlated shadowing.
+/// All directions are local to the surface and point away from it.
+pub struct Ggx {
+    pub alpha: f32,
+}
+
+impl Ggx {
+    /// Uses the common perceptual mapping `alpha = roughness^2`.
+    pub fn from_roughness(roughness: f32) -> Self {
+        Ggx {
+            alpha: (roughness * roughness).max(MIN_ALPHA),
+        }
+    }
+
+    /// Density of microfacet normals `h`.
+    pub fn d(&self, h: Vec3) -> f32 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let alpha_2 = self.alpha * self.alpha;
+        let denominator = h.z * h.z * (alpha_2 - 1.0) + 1.0;
+        alpha_2 / (PI * denominator * denominator)
+    }
+
+    fn lambda(&self, w: Vec3) -> f32 {
+        let cos_2 = w.z * w.z;
+        if cos_2 <= 0.0 {
+            return f32::INFINITY;
+        }
+        let tan_2 = (1.0 - cos_2) / cos_2;
+        ((1.0 + self.alpha * self.alpha * tan_2).sqrt() - 1.0) * 0.5
+    }
+
+    /// Fraction of microfacets visible from `w`.
+    pub fn g1(&self, w: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Fraction of microfacets visible from both `wo` and `wi`, works for transmission too.
+    pub fn g2(&self, wo: Vec3, wi: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Samples a microfacet normal visible from `wo`, which has to be above the surface.
+    /// Based on: <https://doi.org/10.1111/cgf.14867> (Dupuy and Benyoub, spherical caps)
+    pub fn sample_visible_normal(&self, wo: Vec3, seed: &mut u32) -> Vec3 {
+        //stretch so the distribution becomes a hemisphere
+        let wo_std = Vec3::new(wo.x * self.alpha, wo.y * self.alpha, wo.z).normalize();
+
+        let phi = rand_float(seed, (0.0, 2.0 * PI));
+        let z = rand_float(seed, (-wo_std.z, 1.0));
+        let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+        let cap = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), z);
+
+        let h_std = cap + wo_std;
+        Vec3::new(h_std.x * self.alpha, h_std.y * self.alpha, h_std.z.max(0.0)).normalize()
+    }
+
+    /// Pdf of [`Ggx::sample_visible_normal`] returning `h`.
+    pub fn visible_normal_pdf(&self, wo: Vec3, h: Vec3) -> f32 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * wo.dot(h).max(0.0) * self.d(h) / wo.z
+    }
+}
+
+/// Fraction of light reflected off a dielectric interface. `eta` is the ior on the far side
+/// divided by the ior on the side of `cos_i`. Returns 1 for total internal reflection.
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin_t_2 = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin_t_2 >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin_t_2).sqrt();
+
+    let r_s = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let r_p = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    (r_s * r_s + r_p * r_p) * 0.5
+}
+
+/// Schlick's approximation with the color at normal incidence, good enough for conductors.
+pub fn fresnel_schlick(f0: Vec3, cos_i: f32) -> Vec3 {
+    f0 + (Vec3::ONE - f0) * (1.0 - cos_i.clamp(0.0, 1.0)).powi(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUGHNESSES: [f32; 4] = [0.3, 0.5, 0.8, 1.0];
+
+    //midpoint rule over the upper hemisphere in spherical coordinates
+    fn integrate_hemisphere(theta_steps: u32, phi_steps: u32, f: impl Fn(Vec3) -> f32) -> f32 {
+        let d_theta = 0.5 * PI / theta_steps as f32;
+        let d_phi = 2.0 * PI / phi_steps as f32;
+        let mut sum = 0.0f64;
+        for i in 0..theta_steps {
+            let theta = (i as f32 + 0.5) * d_theta;
+            let mut ring = 0.0f64;
+            for j in 0..phi_steps {
+                let phi = (j as f32 + 0.5) * d_phi;
+                let w = Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+                ring += f(w) as f64;
+            }
+            sum += ring * (theta.sin() * d_theta * d_phi) as f64;
+        }
+        sum as f32
+    }
+
+    fn direction(theta: f32) -> Vec3 {
+        Vec3::new(theta.sin(), 0.0, theta.cos())
+    }
+
+    #[test]
+    fn projected_normal_distribution_integrates_to_one() {
+        for roughness in ROUGHNESSES {
+            let ggx = Ggx::from_roughness(roughness);
+            let integral = integrate_hemisphere(4096, 1, |h| ggx.d(h) * h.z);
+            assert!((integral - 1.0).abs() < 1e-3, "roughness {}: {}", roughness, integral);
+        }
+    }
+
+    #[test]
+    fn fresnel_matches_normal_incidence_and_total_internal_reflection() {
+        for eta in [1.33, 1.5, 2.4] {
+            let expected = ((eta - 1.0) / (eta + 1.0)) * ((eta - 1.0) / (eta + 1.0));
+            assert!((fresnel_dielectric(1.0, eta) - expected).abs() < 1e-6);
+            //seen from inside, past the critical angle
+            let critical = (1.0 / eta).asin();
+            assert_eq!(fresnel_dielectric((critical + 0.01).cos(), 1.0 / eta), 1.0);
+            assert!(fresnel_dielectric((critical - 0.01).cos(), 1.0 / eta) < 1.0);
+        }
+    }
+
+    #[test]
+    fn visible_normal_pdf_integrates_to_one() {
+        for roughness in ROUGHNESSES {
+            let ggx = Ggx::from_roughness(roughness);
+            for theta in [0.0, 0.7, 1.3] {
+                let wo = direction(theta);
+                let integral = integrate_hemisphere(1024, 256, |h| ggx.visible_normal_pdf(wo, h));
+                assert!((integral - 1.0).abs() < 1e-2, "roughness {} theta {}: {}", roughness, theta, integral);
+            }
+        }
+    }
+
+    #[test]
+    fn visible_normal_samples_follow_the_pdf() {
+        const BINS: usize = 8;
+        const SAMPLES: u32 = 200_000;
+        let mut seed = 0x2545_f491;
+        for roughness in ROUGHNESSES {
+            let ggx = Ggx::from_roughness(roughness);
+            for theta in [0.3, 1.2] {
+                let wo = direction(theta);
+
+                //histogram over the cosine of the sampled normal
+                let mut histogram = [0u32; BINS];
+                for _ in 0..SAMPLES {
+                    let h = ggx.sample_visible_normal(wo, &mut seed);
+                    histogram[((h.z * BINS as f32) as usize).min(BINS - 1)] += 1;
+                }
+
+                for (bin, count) in histogram.iter().enumerate() {
+                    let expected = integrate_hemisphere(1024, 256, |h| {
+                        if (h.z * BINS as f32) as usize == bin {
+                            ggx.visible_normal_pdf(wo, h)
+                        } else {
+                            0.0
+                        }
+                    });
+                    let measured = *count as f32 / SAMPLES as f32;
+                    assert!(
+                        (measured - expected).abs() < 0.01,
+                        "roughness {} theta {} bin {}: {} sampled, {} expected",
+                        roughness,
+                        theta,
+                        bin,
+                        measured,
+                        expected
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reflection_without_fresnel_loses_energy() {
+        const SAMPLES: u32 = 200_000;
+        let mut seed = 0x2545_f491;
+        for roughness in ROUGHNESSES {
+            let ggx = Ggx::from_roughness(roughness);
+            for theta in [0.0, 0.7, 1.3] {
+                let wo = direction(theta);
+                //white furnace, the specular brdf with a fresnel term of 1 times the cosine
+                let albedo = integrate_hemisphere(1024, 256, |wi| {
+                    let h = (wo + wi).normalize();
+                    ggx.d(h) * ggx.g2(wo, wi) / (4.0 * wo.z)
+                });
+                assert!(albedo <= 1.0 + 1e-2, "roughness {} theta {}: {}", roughness, theta, albedo);
+
+                //the same through the sampling the renderer does, weighted by g2 / g1
+                let mut sampled = 0.0;
+                for _ in 0..SAMPLES {
+                    let h = ggx.sample_visible_normal(wo, &mut seed);
+                    let wi = h * 2.0 * wo.dot(h) - wo;
+                    if wi.z > 0.0 {
+                        sampled += ggx.g2(wo, wi) / ggx.g1(wo);
+                    }
+                }
+                let sampled = sampled / SAMPLES as f32;
+                assert!(
+                    (sampled - albedo).abs() < 1e-2,
+                    "roughness {} theta {}: {} sampled, {} integrated",
+                    roughness,
+                    theta,
+                    sampled,
+                    albedo
+                );
+            }
+        }
+
+        //alpha of 1 at normal incidence integrates to 1 - ln 2 in closed form
+        let albedo = integrate_hemisphere(1024, 1, |wi| {
+            let ggx = Ggx::from_roughness(1.0);
+            let wo = Vec3::Z;
+            ggx.d((wo + wi).normalize()) * ggx.g2(wo, wi) / 4.0
+        });
+        assert!((albedo - (1.0 - 2.0f32.ln())).abs() < 1e-3, "{}", albedo);
+    }
+}
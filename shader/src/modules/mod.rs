@@ -1,10 +1,22 @@
-use shared::{glam::{Affine3A, Mat3}, Bvh, Instance, Object, Vertex};
+use shared::{glam::{Affine3A, Mat3, Vec3}, Bvh, DensityGrid, Instance, LightNode, LightTriangle, Object, PrincipledMaterial, Vertex};
+use spirv_std::image::{Image2d, Image2dArray, SampledImage};
+use spirv_std::Sampler;
 
 use crate::modules::trace::Ray;
 
+pub mod environment;
+pub mod exposure;
 pub mod hit;
+pub mod light;
 pub mod material;
+pub mod medium;
+pub mod microfacet;
+pub mod normal_map;
+pub mod spectrum;
+pub mod texture;
+pub mod tonemap;
 pub mod trace;
+pub mod volume;
 
 pub fn get_seed(
     frame: u32,
@@ -48,6 +60,35 @@ pub fn rand_float(seed: &mut u32, range: (f32, f32)) -> f32 {
     (*seed & 65535) as f32 / 65535.0 * (range.1 - range.0) + range.0
 }
 
+/// Builds two vectors that together with `normal` form an orthonormal basis.
+/// Based on: <https://graphics.pixar.com/library/OrthonormalB/paper.pdf>
+pub fn orthonormal_basis(normal: Vec3) -> (Vec3, Vec3) {
+    let sign = if normal.z >= 0.0 { 1.0 } else { -1.0 };
+    let a = -1.0 / (sign + normal.z);
+    let b = normal.x * normal.y * a;
+    (
+        Vec3::new(1.0 + sign * normal.x * normal.x * a, sign * b, -sign * normal.x),
+        Vec3::new(b, sign + normal.y * normal.y * a, -normal.y),
+    )
+}
+
+/// Barycentric coordinates of `point` inside the triangle `(a, b, c)`,
+/// the weights of `a`, `b` and `c` in that order.
+pub fn barycentric(point: Vec3, a: Vec3, b: Vec3, c: Vec3) -> Vec3 {
+    let edge_1 = b - a;
+    let edge_2 = c - a;
+    let to_point = point - a;
+    let d11 = edge_1.dot(edge_1);
+    let d12 = edge_1.dot(edge_2);
+    let d22 = edge_2.dot(edge_2);
+    let dp1 = to_point.dot(edge_1);
+    let dp2 = to_point.dot(edge_2);
+    let denominator = d11 * d22 - d12 * d12;
+    let v = (d22 * dp1 - d12 * dp2) / denominator;
+    let w = (d11 * dp2 - d12 * dp1) / denominator;
+    Vec3::new(1.0 - v - w, v, w)
+}
+
 pub fn is_nan(value: f32) -> bool {
     //bitmask because actual checks are optimized out by the compiler
     let bitmask = value.to_bits();
@@ -83,4 +124,14 @@ pub struct ObjectInfo<'a> {
     pub object_buffer: &'a [Object],
     pub instance_buffer: &'a [Instance],
     pub bvh_buffer: &'a [Bvh],
+    pub environment_map: &'a SampledImage<Image2d>,
+    pub environment_cdf: &'a [f32],
+    pub textures: &'a Image2dArray,
+    /// indexed by [`shared::WrapMode`]
+    pub texture_samplers: &'a [Sampler; 3],
+    pub material_buffer: &'a [PrincipledMaterial],
+    pub light_buffer: &'a [LightTriangle],
+    pub light_tree: &'a [LightNode],
+    pub density_grids: &'a [DensityGrid],
+    pub densities: &'a [f32],
 }
@@ -0,0 +1,81 @@
+use super::texture::sample_texture;
+use super::ObjectInfo;
+use shared::glam::{Vec3, Vec4};
+use shared::{SceneInfo, TextureSlot};
+#[allow(unused_imports)] //actually used for .sqrt because we don't allow std
+use spirv_std::num_traits::Float;
+
+/// Interpolated shading frame at a hit point, normal and bump maps are given relative to it.
+pub struct TangentFrame {
+    pub normal: Vec3,
+    /// zero if the mesh has no usable uv mapping at this point
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+}
+
+impl TangentFrame {
+    /// `tangent.w` holds the handedness of the bitangent, like the vertex tangents.
+    pub fn new(normal: Vec3, tangent: Vec4) -> Self {
+        let tangent_3 = tangent.truncate();
+        //interpolation breaks orthogonality, so it is restored here
+        let tangent_3 = (tangent_3 - normal * normal.dot(tangent_3)).normalize_or_zero();
+        let handedness = if tangent.w < 0.0 { -1.0 } else { 1.0 };
+
+        TangentFrame {
+            normal,
+            tangent: tangent_3,
+            bitangent: normal.cross(tangent_3) * handedness,
+        }
+    }
+
+    pub fn has_tangent(&self) -> bool {
+        self.tangent != Vec3::ZERO
+    }
+
+    pub fn to_world(&self, local: Vec3) -> Vec3 {
+        (self.tangent * local.x + self.bitangent * local.y + self.normal * local.z).normalize()
+    }
+}
+
+/// Shading normal read from a tangent space normal map, in the usual +y convention.
+pub fn apply_normal_map(
+    frame: &TangentFrame,
+    texture: TextureSlot,
+    uv: (f32, f32),
+    objects: &ObjectInfo,
+) -> Vec3 {
+    if texture.is_none() || !frame.has_tangent() {
+        return frame.normal;
+    }
+
+    let texel = sample_texture(texture, uv, objects).truncate();
+    frame.to_world(texel * 2.0 - Vec3::ONE)
+}
+
+/// Shading normal tilted along the slope of a height map, measured with one texel
+/// finite differences. `strength` is how far a full black to white step tilts it.
+pub fn apply_bump_map(
+    frame: &TangentFrame,
+    texture: TextureSlot,
+    strength: f32,
+    uv: (f32, f32),
+    scene_info: &SceneInfo,
+    objects: &ObjectInfo,
+) -> Vec3 {
+    if texture.is_none() || !frame.has_tangent() {
+        return frame.normal;
+    }
+
+    let du = 1.0 / scene_info.texture_width as f32;
+    let dv = 1.0 / scene_info.texture_height as f32;
+    let height = sample_texture(texture, uv, objects).x;
+    let height_u = sample_texture(texture, (uv.0 + du, uv.1), objects).x;
+    //uvs run down the image while the bitangent points up it
+    let height_v = sample_texture(texture, (uv.0, uv.1 - dv), objects).x;
+
+    frame.to_world(Vec3::new(
+        -(height_u - height) * strength,
+        -(height_v - height) * strength,
+        1.0,
+    ))
+}
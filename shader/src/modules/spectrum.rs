@@ -0,0 +1,159 @@
+use shared::glam::{Mat3, Vec3, Vec4};
+use shared::{ColorMode, D_LINE_WAVELENGTH};
+
+use super::rand_float;
+#[allow(unused_imports)] //actually used for .exp because we don't allow std
+use spirv_std::num_traits::Float;
+
+/// Visible range the wavelengths are picked from, in nanometers.
+const WAVELENGTH_MIN: f32 = 380.0;
+const WAVELENGTH_MAX: f32 = 780.0;
+
+/// Linear srgb of a flat spectrum, so it can be made white again.
+const WHITE: Vec3 = Vec3::new(128.3627, 101.5486, 97.0496);
+
+//undoes the small mix of channels the basis spectra below get back out as
+const BASIS_FROM_RGB: Mat3 = Mat3::from_cols(
+    Vec3::new(0.979567, -0.001649, 0.024282),
+    Vec3::new(-0.010436, 1.008408, 0.073203),
+    Vec3::new(0.030869, -0.006759, 0.902515),
+);
+
+const XYZ_TO_RGB: Mat3 = Mat3::from_cols(
+    Vec3::new(3.2406, -0.9689, 0.0557),
+    Vec3::new(-1.5372, 1.8758, -0.2040),
+    Vec3::new(-0.4986, 0.0415, 1.0570),
+);
+
+/// What a path carries back to the camera, either per color channel or per wavelength.
+/// The rest of the tracer works in rgb and gets turned into a spectrum only here.
+pub struct Path {
+    pub spectral: bool,
+    /// the first one is the hero wavelength, the others are spread evenly from it
+    pub wavelengths: Vec4,
+    /// what the light found further along the path gets multiplied by before reaching the camera
+    pub throughput: Vec4,
+    pub radiance: Vec4,
+}
+
+impl Path {
+    pub fn new(mode: ColorMode, seed: &mut u32) -> Self {
+        let spectral = mode == ColorMode::Spectral;
+        let wavelengths = if spectral {
+            //hero wavelength sampling
+            //Based on: Wilkie et al., "Hero Wavelength Spectral Sampling"
+            let hero = rand_float(seed, (0.0, 1.0));
+            let offsets = (Vec4::new(0.0, 0.25, 0.5, 0.75) + hero).fract();
+            Vec4::splat(WAVELENGTH_MIN) + offsets * (WAVELENGTH_MAX - WAVELENGTH_MIN)
+        } else {
+            Vec4::splat(D_LINE_WAVELENGTH)
+        };
+        Path {
+            spectral,
+            wavelengths,
+            throughput: Vec4::ONE,
+            radiance: Vec4::ZERO,
+        }
+    }
+
+    /// The wavelength dispersive materials refract with.
+    pub fn hero_wavelength(&self) -> f32 {
+        self.wavelengths.x
+    }
+
+    fn spectrum(&self, color: Vec3) -> Vec4 {
+        if self.spectral {
+            rgb_to_spectrum(color, self.wavelengths)
+        } else {
+            color.extend(0.0)
+        }
+    }
+
+    pub fn attenuate(&mut self, color: Vec3) {
+        self.throughput *= self.spectrum(color);
+    }
+
+    pub fn add_light(&mut self, color: Vec3) {
+        self.radiance += self.throughput * self.spectrum(color);
+    }
+
+    /// For the debug views, which replace whatever the path found.
+    pub fn set_color(&mut self, color: Vec3) {
+        self.spectral = false;
+        self.radiance = color.extend(0.0);
+    }
+
+    /// After a refraction that depends on the wavelength, the others would have gone
+    /// somewhere else, so only the hero one is followed further.
+    pub fn keep_hero_wavelength(&mut self) {
+        if self.spectral {
+            self.throughput = Vec4::new(self.throughput.x * 4.0, 0.0, 0.0, 0.0);
+        }
+    }
+
+    pub fn max_throughput(&self) -> f32 {
+        self.throughput.max_element()
+    }
+
+    pub fn scale_throughput(&mut self, scale: f32) {
+        self.throughput *= scale;
+    }
+
+    pub fn rgb(&self) -> Vec3 {
+        if self.spectral {
+            spectrum_to_rgb(self.radiance, self.wavelengths)
+        } else {
+            self.radiance.truncate()
+        }
+    }
+}
+
+fn smoothstep(edge_0: f32, edge_1: f32, x: f32) -> f32 {
+    let t = ((x - edge_0) / (edge_1 - edge_0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Smooth spectrum that comes out as roughly the same linear srgb color, made of a red, a
+/// green and a blue basis spectrum that add up to a flat one, so white stays flat.
+fn rgb_to_spectrum(color: Vec3, wavelengths: Vec4) -> Vec4 {
+    let weights = BASIS_FROM_RGB * color;
+    let mut spectrum = Vec4::ZERO;
+    for i in 0..4 {
+        let red = smoothstep(575.0, 600.0, wavelengths[i]);
+        let blue = 1.0 - smoothstep(490.0, 510.0, wavelengths[i]);
+        let value = weights.x * red + weights.y * (1.0 - red - blue) + weights.z * blue;
+        //saturated colors can ask for a little negative light
+        spectrum[i] = value.max(0.0);
+    }
+    spectrum
+}
+
+fn lobe(wavelength: f32, mean: f32, width_below: f32, width_above: f32) -> f32 {
+    let width = if wavelength < mean { width_below } else { width_above };
+    let x = (wavelength - mean) / width;
+    (-0.5 * x * x).exp()
+}
+
+/// CIE 1931 color matching functions.
+/// Based on: Wyman, Sloan and Shirley, "Simple Analytic Approximations to the CIE XYZ Color
+/// Matching Functions"
+fn color_matching(wavelength: f32) -> Vec3 {
+    Vec3::new(
+        1.056 * lobe(wavelength, 599.8, 37.9, 31.0) + 0.362 * lobe(wavelength, 442.0, 16.0, 26.7)
+            - 0.065 * lobe(wavelength, 501.1, 20.4, 26.2),
+        0.821 * lobe(wavelength, 568.8, 46.9, 40.5) + 0.286 * lobe(wavelength, 530.9, 16.3, 31.1),
+        1.217 * lobe(wavelength, 437.0, 11.8, 36.0) + 0.681 * lobe(wavelength, 459.0, 26.0, 13.8),
+    )
+}
+
+/// Linear srgb estimate from radiance at the given wavelengths, white balanced so a flat
+/// spectrum is white.
+fn spectrum_to_rgb(radiance: Vec4, wavelengths: Vec4) -> Vec3 {
+    let mut xyz = Vec3::ZERO;
+    for i in 0..4 {
+        xyz += color_matching(wavelengths[i]) * radiance[i];
+    }
+    //the wavelengths are uniform over the range, divided by their pdf and averaged
+    xyz *= (WAVELENGTH_MAX - WAVELENGTH_MIN) / 4.0;
+    XYZ_TO_RGB * xyz / WHITE
+}
@@ -0,0 +1,35 @@
+use super::ObjectInfo;
+use shared::glam::{Vec3, Vec4};
+use shared::TextureSlot;
+#[allow(unused_imports)] //actually used for .powf because we don't allow std
+use spirv_std::num_traits::Float;
+
+/// Bilinearly filtered texel of `slot` at `uv`, wrapped with the slot's wrap mode.
+/// Returns white for materials without a texture so the result can always be multiplied in.
+pub fn sample_texture(slot: TextureSlot, uv: (f32, f32), objects: &ObjectInfo) -> Vec4 {
+    if slot.is_none() {
+        return Vec4::ONE;
+    }
+
+    let sampler = objects.texture_samplers[slot.wrap_mode as usize];
+    objects
+        .textures
+        .sample_by_lod(sampler, Vec3::new(uv.0, uv.1, slot.index as f32), 0.0)
+}
+
+/// Colour textures are stored in srgb, lighting has to happen in linear space.
+pub fn srgb_to_linear(color: Vec3) -> Vec3 {
+    Vec3::new(
+        srgb_channel_to_linear(color.x),
+        srgb_channel_to_linear(color.y),
+        srgb_channel_to_linear(color.z),
+    )
+}
+
+fn srgb_channel_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
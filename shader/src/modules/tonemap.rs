@@ -0,0 +1,82 @@
+use shared::glam::{Mat3, Vec3};
+use shared::{OutputEncoding, ToneMapping};
+#[allow(unused_imports)] //actually used for .log2 because we don't allow std
+use spirv_std::num_traits::Float;
+
+//Based on: Hill, "BakingLab", a fit of the aces reference rendering and output transforms
+const ACES_INPUT: Mat3 = Mat3::from_cols(
+    Vec3::new(0.59719, 0.07600, 0.02840),
+    Vec3::new(0.35458, 0.90834, 0.13383),
+    Vec3::new(0.04823, 0.01566, 0.83777),
+);
+const ACES_OUTPUT: Mat3 = Mat3::from_cols(
+    Vec3::new(1.60475, -0.10208, -0.00327),
+    Vec3::new(-0.53108, 1.10813, -0.07276),
+    Vec3::new(-0.07367, -0.00605, 1.07602),
+);
+
+//Based on: Wrensch, "Minimal AgX Implementation"
+const AGX_INSET: Mat3 = Mat3::from_cols(
+    Vec3::new(0.8424791, 0.04232824, 0.04237565),
+    Vec3::new(0.0784336, 0.8784686, 0.0784336),
+    Vec3::new(0.07922375, 0.07916613, 0.879143),
+);
+const AGX_OUTSET: Mat3 = Mat3::from_cols(
+    Vec3::new(1.196879, -0.05289685, -0.05297164),
+    Vec3::new(-0.09802088, 1.151903, -0.09804345),
+    Vec3::new(-0.09902974, -0.09896118, 1.151074),
+);
+//stops around middle grey the agx curve covers
+const AGX_MIN_EV: f32 = -12.47393;
+const AGX_MAX_EV: f32 = 4.026069;
+
+//Based on: Hable, "Uncharted 2: HDR Lighting"
+const FILMIC_WHITE: f32 = 11.2;
+const FILMIC_EXPOSURE_BIAS: f32 = 2.0;
+
+/// Squeezes exposed linear radiance into the displayable range, still linear.
+pub fn tone_map(color: Vec3, operator: ToneMapping) -> Vec3 {
+    let color = color.max(Vec3::ZERO);
+    if operator == ToneMapping::Reinhard {
+        //on luminance, so bright colors don't wash out to white as fast as per channel
+        let luminance = color.dot(Vec3::new(0.2126, 0.7152, 0.0722));
+        (color / (1.0 + luminance)).min(Vec3::ONE)
+    } else if operator == ToneMapping::Filmic {
+        let curve = filmic_curve(color * FILMIC_EXPOSURE_BIAS);
+        (curve / filmic_curve(Vec3::splat(FILMIC_WHITE))).min(Vec3::ONE)
+    } else if operator == ToneMapping::Aces {
+        let v = ACES_INPUT * color;
+        let fitted = (v * (v + 0.0245786) - 0.000090537) / (v * (v * 0.983729 + 0.432951) + 0.238081);
+        (ACES_OUTPUT * fitted).clamp(Vec3::ZERO, Vec3::ONE)
+    } else if operator == ToneMapping::Agx {
+        agx(color)
+    } else {
+        color.min(Vec3::ONE)
+    }
+}
+
+/// Display encode of tone mapped linear srgb, the only one between the scene and the screen.
+pub fn encode(color: Vec3, encoding: OutputEncoding) -> Vec3 {
+    Vec3::new(encoding.encode(color.x), encoding.encode(color.y), encoding.encode(color.z))
+}
+
+fn filmic_curve(x: Vec3) -> Vec3 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    (x * (x * a + c * b) + d * e) / (x * (x * a + b) + d * f) - e / f
+}
+
+fn agx(color: Vec3) -> Vec3 {
+    let v = (AGX_INSET * color).max(Vec3::splat(1e-10));
+    let v = Vec3::new(v.x.log2(), v.y.log2(), v.z.log2()).clamp(Vec3::splat(AGX_MIN_EV), Vec3::splat(AGX_MAX_EV));
+    let x = (v - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV);
+
+    //polynomial fit of the default agx contrast curve
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let curve = x4 * x2 * 15.5 - x4 * x * 40.14 + x4 * 31.96 - x2 * x * 6.868 + x2 * 0.4298 + x * 0.1191
+        - 0.00232;
+
+    //the curve comes out display encoded, it is taken back to linear like the other operators
+    let display = (AGX_OUTSET * curve).clamp(Vec3::ZERO, Vec3::ONE);
+    Vec3::new(display.x.powf(2.2), display.y.powf(2.2), display.z.powf(2.2))
+}
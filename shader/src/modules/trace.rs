@@ -1,41 +1,85 @@
+use super::environment::Environment;
 use super::hit::*;
+use super::light::{light_triangle_pdf, sample_light_triangle, Sun};
 use super::material::*;
+use super::medium::{HenyeyGreenstein, Medium, MediumStack};
+use super::volume::{GridMedium, TRACKING_LOST};
+use super::spectrum::Path;
+use super::normal_map::TangentFrame;
+use super::barycentric;
 use super::rand_float;
 use super::ObjectInfo;
 use shared::glam::Vec3;
 use shared::glam::Vec4;
 use shared::BoundingBox;
+use shared::exp;
 use shared::CamData;
+use shared::MaterialModel;
+use shared::Projection;
+use shared::NO_DENSITY_GRID;
+use shared::PrincipledMaterial;
 //use crate::Resources;
 use core::f32::consts::PI;
 #[allow(unused_imports)]
 use spirv_std::num_traits::Float;
 
-// const MATERIAL_0: RefractiveMaterial = RefractiveMaterial::new(Vec3::new(0.9, 0.9, 0.9), 1.33);
-const MATERIAL_0: GenericMaterial = GenericMaterial {
-    color: Vec3::new(1.0, 1.0, 1.0),
-    specular: 0.0,
-    specular_roughness: 0.0,
-    roughness: 0.0,
-    ior: 1.5,
-};
-const MATERIAL_1: NormalMaterial = NormalMaterial {};
-const MATERIAL_2: EmmissiveMaterial = EmmissiveMaterial::new(Vec3::new(15.0, 15.0, 15.0));
-
-pub fn claculate_vec_dir_from_cam(data: &CamData, (pix_x, pix_y): (f32, f32)) -> Ray {
-    //fov is counted in degrees in the horizontal direction
-    let fov = (data.fov * PI / 180.0) / 2.0;
-    let edge_dist = fov.tan();
-    let pix_x_frac = (pix_x / data.canvas_width as f32) * 2.0 - 1.0;
-    let pix_y_frac = (pix_y / data.canvas_height as f32) * 2.0 - 1.0;
-    let pix_y_frac_adjusted = pix_y_frac * (data.canvas_height as f32 / data.canvas_width as f32);
-    let pix_x_dist = pix_x_frac * edge_dist;
-    let pix_y_dist = pix_y_frac_adjusted * edge_dist;
-    let orientation_vec = Vec3::new(pix_x_dist, pix_y_dist, 1.0);
-    let orientation_vec = data.transform.transform_vector3(orientation_vec);
+//debug views, everything else comes from the material buffer
+const NORMAL_MATERIAL: NormalMaterial = NormalMaterial {};
+const UV_MATERIAL: UVMaterial = UVMaterial {};
+
+//how far shadow rays start above the surface they leave from
+const SHADOW_RAY_OFFSET: f32 = 1e-4;
+//boundary crossings shadow rays follow through a single volume
+const MAX_VOLUME_CROSSINGS: u32 = 8;
+
+/// Camera ray through a pixel, a zero orientation where the projection shows nothing.
+pub fn claculate_vec_dir_from_cam(data: &CamData, pixel: (f32, f32), seed: &mut u32) -> Ray {
+    let (origin, orientation_vec) = data.view_ray(pixel);
+
+    //thin lens, rays from all over the aperture meet again on the plane in focus. Only the
+    //flat projections have one.
+    let flat = data.projection == Projection::Perspective || data.projection == Projection::Orthographic;
+    let lens = if flat && data.aperture_radius > 0.0 {
+        let (x, y) = sample_aperture(data.aperture_blades, seed);
+        Vec3::new(x, y, 0.0) * data.aperture_radius
+    } else {
+        Vec3::ZERO
+    };
+    let direction = if lens == Vec3::ZERO {
+        orientation_vec
+    } else {
+        orientation_vec * data.focus_distance - lens
+    };
+
     Ray::new(
-        data.transform.transform_point3(Vec3::new(0.0, 0.0, 0.0)),
-        orientation_vec,
+        data.transform.transform_point3(origin + lens),
+        data.transform.transform_vector3(direction),
+    )
+}
+
+/// Uniform point on the unit disk, or on the regular polygon with `blades` corners inscribed in it.
+fn sample_aperture(blades: u32, seed: &mut u32) -> (f32, f32) {
+    if blades < 3 {
+        let radius = rand_float(seed, (0.0, 1.0)).sqrt();
+        let angle = rand_float(seed, (0.0, 2.0 * PI));
+        return (radius * angle.cos(), radius * angle.sin());
+    }
+
+    //the polygon is a fan of equal triangles around the center, one of them is picked and
+    //sampled uniformly
+    let wedge = 2.0 * PI / blades as f32;
+    let index = (rand_float(seed, (0.0, blades as f32)) as u32).min(blades - 1);
+    let mut u = rand_float(seed, (0.0, 1.0));
+    let mut v = rand_float(seed, (0.0, 1.0));
+    if u + v > 1.0 {
+        u = 1.0 - u;
+        v = 1.0 - v;
+    }
+    let angle_1 = wedge * index as f32;
+    let angle_2 = angle_1 + wedge;
+    (
+        u * angle_1.cos() + v * angle_2.cos(),
+        u * angle_1.sin() + v * angle_2.sin(),
     )
 }
 
@@ -45,6 +89,29 @@ pub fn vector_angle(lhs: Vec4, rhs: Vec4) -> f32 {
     (dot_product / len_product).acos()
 }
 
+/// The bounce that shot the current ray, lights it finds are weighed against having been
+/// sampled directly from there.
+#[derive(Clone, Copy)]
+pub struct Scatter {
+    /// of the direction the ray was shot in
+    pub pdf: f32,
+    /// where it was shot from, the ray itself moves on past surfaces it passes through
+    pub pos: Vec3,
+    /// shading normal where it was shot from
+    pub normal: Vec3,
+    pub lobe: Lobe,
+}
+
+impl Scatter {
+    //nothing could have sampled the lights before camera rays
+    pub const CAMERA: Self = Scatter {
+        pdf: 0.0,
+        pos: Vec3::ZERO,
+        normal: Vec3::ZERO,
+        lobe: Lobe::Diffuse,
+    };
+}
+
 #[derive(Clone, Copy)]
 pub struct Ray {
     pub pos: Vec3,
@@ -67,7 +134,9 @@ impl Ray {
         scene_info: &shared::SceneInfo,
         seed: &mut u32,
         cam_data: &CamData,
-        color: &mut Vec3,
+        path: &mut Path,
+        scatter: &mut Scatter,
+        media: &mut MediumStack,
         objects: &ObjectInfo,
     ) -> RayReturnState {
         self.normalize();
@@ -81,7 +150,7 @@ impl Ray {
                 verts: objects.vertex_buffer,
                 tris: objects.triangle_buffer,
                 bvh_buffer: objects.bvh_buffer,
-                material_id: i as u32,
+                material_id: instance.material_id,
                 bvh_root: object.bvh_root,
             };
             let inverse_matrix = instance.transform.inverse();
@@ -91,20 +160,20 @@ impl Ray {
             };
 
             let clamp = (f32::EPSILON, record.t);
-            mesh.hit(&ray, clamp, &mut record, i as u32, get_backface_culling(i as u32));
+            mesh.hit(&ray, clamp, &mut record, i as u32, get_backface_culling(i as u32, objects));
         }
 
         #[cfg(feature = "debug")]
         if cam_data.debug_information == shared::DebugInformation::TriangleIntersection {
             if record.triangle_tests > cam_data.debug_number {
-                *color = Vec3::new(1.0, 0.0, 0.0);
+                path.set_color(Vec3::new(1.0, 0.0, 0.0));
             } else {
                 let color_ = Vec3::new(
                     record.triangle_tests as f32 / cam_data.debug_number as f32,
                     record.triangle_tests as f32 / cam_data.debug_number as f32,
                     record.triangle_tests as f32 / cam_data.debug_number as f32,
                 );
-                *color = color_;
+                path.set_color(color_);
             }
             return RayReturnState::Stop;
         }
@@ -112,28 +181,70 @@ impl Ray {
         #[cfg(feature = "debug")]
         if cam_data.debug_information == shared::DebugInformation::BvhIntersection {
             if record.box_tests > cam_data.debug_number {
-                *color = Vec3::new(1.0, 0.0, 0.0);
+                path.set_color(Vec3::new(1.0, 0.0, 0.0));
             } else {
                 let color_ = Vec3::new(
                     record.box_tests as f32 / cam_data.debug_number as f32,
                     record.box_tests as f32 / cam_data.debug_number as f32,
                     record.box_tests as f32 / cam_data.debug_number as f32,
                 );
-                *color = color_;
+                path.set_color(color_);
             }
             return RayReturnState::Stop;
         }
 
         if record.t == f32::INFINITY {
-            let sky_material = BackgroundMaterial {};
-
-            let stop_col =
-                sky_material.get_stop_color(self.orientation, (0.0, 0.0), self.orientation);
-            *color = stop_col;
+            let sky_material = BackgroundMaterial::new(scene_info);
+            let environment = Environment::new(scene_info, objects);
+
+            //the sun and the environment map were also sampled directly at the last bounce,
+            //so both ways of finding them are weighed against each other. the map replaces the
+            //whole sky, sun included, it has its own if it was taken outside
+            let stop_col = if !environment.enabled() {
+                sky_material.get_stop_color(self.orientation, (0.0, 0.0), self.orientation)
+                    + sky_material.sun.radiance(self.orientation)
+                        * light_hit_weight(scatter.pdf, sky_material.sun.pdf(self.orientation))
+            } else {
+                environment.radiance(self.orientation)
+                    * light_hit_weight(scatter.pdf, environment.pdf(self.orientation))
+            };
+            path.add_light(stop_col);
 
             return RayReturnState::Stop;
         }
 
+        let medium = media.current();
+        if medium.grid != NO_DENSITY_GRID {
+            let grid = GridMedium::new(&medium, objects);
+            let distance = grid.sample_collision(self.pos, self.orientation, record.t, seed, objects);
+            if distance == TRACKING_LOST {
+                return RayReturnState::Absorb;
+            }
+            if distance < record.t {
+                path.attenuate(medium.scattering / medium.extinction().max_element());
+                *self = Ray::new(self.pos + self.orientation * distance, self.orientation);
+                return self.scatter_in_medium(&medium, path, scatter, scene_info, seed, objects);
+            }
+        } else if medium.scattering != Vec3::ZERO {
+            //free flight, how far the ray gets before the medium scatters it, picked with the
+            //extinction of one of the channels and weighed by all of them
+            let extinction = medium.extinction();
+            let channel = (rand_float(seed, (0.0, 3.0)) as usize).min(2);
+            let distance = -(1.0 - rand_float(seed, (0.0, 1.0))).max(1e-6).ln() / extinction[channel];
+            if distance < record.t {
+                let transmittance = exp(-extinction * distance);
+                let pdf = (extinction * transmittance).dot(Vec3::splat(1.0 / 3.0));
+                path.attenuate(medium.scattering * transmittance / pdf);
+                *self = Ray::new(self.pos + self.orientation * distance, self.orientation);
+                return self.scatter_in_medium(&medium, path, scatter, scene_info, seed, objects);
+            }
+            let transmittance = exp(-extinction * record.t);
+            path.attenuate(transmittance / transmittance.dot(Vec3::splat(1.0 / 3.0)));
+        } else if medium.absorption != Vec3::ZERO {
+            //beer-lambert, light gets absorbed along the whole way through the inside of a material
+            path.attenuate(exp(-medium.absorption * record.t));
+        }
+
         let instance = &objects.instance_buffer[record.instance_id as usize];
         let transform = &instance.transform;
 
@@ -147,29 +258,432 @@ impl Ray {
             vert_3.pos = transform.transform_point3(vert_3.pos);
             (vert_1, vert_2, vert_3)
         };
-        let material_id = record.instance_id as usize;
+        let material = get_material(record.instance_id, objects);
         let ray = *self;
+        let wo = -ray.orientation;
 
-        let normal = {
+        let face_normal = {
             let a = triangle.0.pos - triangle.1.pos;
             let b = triangle.0.pos - triangle.2.pos;
             a.cross(b).normalize()
         };
 
+        let hit = ray.pos + ray.orientation * record.t;
+        let weights = barycentric(hit, triangle.0.pos, triangle.1.pos, triangle.2.pos);
+
+        let uv = {
+            let uv = triangle.0.uv * weights.x + triangle.1.uv * weights.y + triangle.2.uv * weights.z;
+            (uv.x, uv.y)
+        };
+
+        let frame = {
+            let normal = triangle.0.normal * weights.x
+                + triangle.1.normal * weights.y
+                + triangle.2.normal * weights.z;
+            //meshes without normals are shaded flat
+            let normal = if normal.length_squared() > 0.0 {
+                (transform.matrix3.inverse().transpose() * normal).normalize()
+            } else {
+                face_normal
+            };
+
+            let tangent = triangle.0.tangent * weights.x
+                + triangle.1.tangent * weights.y
+                + triangle.2.tangent * weights.z;
+            //mirroring instances flips the bitangent
+            let handedness = tangent.w * transform.matrix3.determinant().signum();
+            TangentFrame::new(normal, transform.transform_vector3(tangent.truncate()).extend(handedness))
+        };
+
+        //the debug views are never normal mapped
+        let normal = if material.model == MaterialModel::Principled {
+            material.shading_normal(&frame, uv, scene_info, objects)
+        } else {
+            frame.normal
+        };
+
+        let outside = media.outside(record.instance_id);
+        let ior = material.ior_at(path.hero_wavelength());
+        let surface = HitMaterial::new(material, uv, ior, outside.ior, objects);
+        let entering = wo.dot(normal) >= 0.0;
+        let inside = Medium::new(record.instance_id, material, ior, surface.absorption());
+
+        //inside something with a higher priority the surface doesn't exist, the path goes on
+        //straight through it and only remembers which side it is on, same as through the
+        //bounds of volumes. `scatter` is left alone, lights found further on are still weighed
+        //from the last real bounce
+        let transmissive = material.model == MaterialModel::Principled && material.transmission > 0.0;
+        let hidden = transmissive && outside.priority > material.priority;
+        if hidden || material.model == MaterialModel::Volume {
+            if entering {
+                media.enter(inside);
+            } else {
+                media.leave(record.instance_id);
+            }
+            *self = Ray::new(hit, ray.orientation);
+            return RayReturnState::Pass;
+        }
+
+        //a random walk under a subsurface material that reaches the surface again comes out
+        //diffusely, with whatever color it picked up on the way
+        let subsurface = material.model == MaterialModel::Principled && material.subsurface > 0.0;
+        if subsurface && !entering {
+            media.leave(record.instance_id);
+            let exit = SubsurfaceExit {};
+
+            let light = Self::sample_sun(hit, face_normal, wo, normal, uv, &exit, scene_info, seed, objects)
+                + Self::sample_environment(hit, face_normal, wo, normal, uv, &exit, scene_info, seed, objects)
+                + Self::sample_emitters(hit, face_normal, wo, normal, uv, &exit, scene_info, seed, objects);
+            path.add_light(light);
+
+            let sample = exit.sample(wo, normal, uv, seed, objects);
+            *scatter = Scatter {
+                pdf: sample.pdf,
+                pos: hit,
+                normal,
+                lobe: sample.lobe,
+            };
+            *self = Ray::new(hit, sample.direction);
+            return RayReturnState::Ray;
+        }
+
+        let emission = surface.emission(wo, normal, uv, objects);
+        if emission != Vec3::ZERO {
+            //emissive triangles were sampled directly at the last bounce as well, from where it
+            //was rather than from the last surface the ray passed through
+            let light_pdf = light_triangle_pdf(
+                record.instance_id,
+                record.triangle_id,
+                scatter.pos,
+                scatter.normal,
+                (hit - scatter.pos).length(),
+                face_normal.dot(wo).abs(),
+                scene_info,
+                objects,
+            );
+            path.add_light(emission * light_hit_weight(scatter.pdf, light_pdf));
+        }
+
+        let light = Self::sample_sun(hit, face_normal, wo, normal, uv, &surface, scene_info, seed, objects)
+            + Self::sample_environment(hit, face_normal, wo, normal, uv, &surface, scene_info, seed, objects)
+            + Self::sample_emitters(hit, face_normal, wo, normal, uv, &surface, scene_info, seed, objects);
+        path.add_light(light);
+
+        let sample = surface.sample(wo, normal, uv, seed, objects);
+        if sample.absorbed() {
+            return RayReturnState::Absorb;
+        }
+        path.attenuate(sample.weight);
+        //reflections stay on the side they came from, refractions enter or leave the material
+        if sample.lobe == Lobe::Transmission {
+            if material.abbe_number > 0.0 {
+                path.keep_hero_wavelength();
+            }
+            if entering {
+                media.enter(inside);
+            } else {
+                media.leave(record.instance_id);
+            }
+        }
+        *scatter = Scatter {
+            pdf: sample.pdf,
+            pos: hit,
+            normal,
+            lobe: sample.lobe,
+        };
+        *self = Ray::new(hit, sample.direction);
+
+        RayReturnState::Ray
+    }
+
+    /// Scatters the ray off a point inside `medium` it has been moved to, lighting it the same
+    /// way as surfaces with the phase function in place of the bsdf.
+    fn scatter_in_medium(
+        &mut self,
+        medium: &Medium,
+        path: &mut Path,
+        scatter: &mut Scatter,
+        scene_info: &shared::SceneInfo,
+        seed: &mut u32,
+        objects: &ObjectInfo,
+    ) -> RayReturnState {
+        let phase = HenyeyGreenstein::new(medium.anisotropy);
+        let pos = self.pos;
+        let wo = -self.orientation;
+        //there is no surface, so no normal to offset shadow rays or shade with
+        let normal = Vec3::ZERO;
         let uv = (0.0, 0.0);
 
-        let mat_return = if material_id == 0 {
-            MATERIAL_0.bxdf(*color, ray, normal, uv, record.t, seed)
-        } else if material_id == 1 {
-            MATERIAL_1.bxdf(*color, ray, normal, uv, record.t, seed)
+        let light = Self::sample_sun(pos, normal, wo, normal, uv, &phase, scene_info, seed, objects)
+            + Self::sample_environment(pos, normal, wo, normal, uv, &phase, scene_info, seed, objects)
+            + Self::sample_emitters(pos, normal, wo, normal, uv, &phase, scene_info, seed, objects);
+        path.add_light(light);
+
+        let sample = phase.sample(wo, normal, uv, seed, objects);
+        *scatter = Scatter {
+            pdf: sample.pdf,
+            pos,
+            normal,
+            lobe: if medium.subsurface {
+                Lobe::Subsurface
+            } else {
+                sample.lobe
+            },
+        };
+        *self = Ray::new(pos, sample.direction);
+
+        RayReturnState::Ray
+    }
+
+    /// Ray towards a light that starts just off the surface, on the side it leaves through.
+    fn shadow_ray(pos: Vec3, face_normal: Vec3, direction: Vec3) -> Ray {
+        let offset = if direction.dot(face_normal) > 0.0 {
+            face_normal
         } else {
-            MATERIAL_2.bxdf(*color, ray, normal, uv, record.t, seed)
+            -face_normal
         };
+        Ray::new(pos + offset * SHADOW_RAY_OFFSET, direction)
+    }
+
+    /// Light the sun sends towards `wo` off the surface at `pos`, none while an environment map
+    /// replaces the sky.
+    fn sample_sun<M: Material>(
+        pos: Vec3,
+        face_normal: Vec3,
+        wo: Vec3,
+        normal: Vec3,
+        uv: (f32, f32),
+        material: &M,
+        scene_info: &shared::SceneInfo,
+        seed: &mut u32,
+        objects: &ObjectInfo,
+    ) -> Vec3 {
+        if Environment::new(scene_info, objects).enabled() {
+            return Vec3::ZERO;
+        }
+
+        let sun = Sun::new(scene_info);
+        let direction = sun.sample_direction(seed);
+        let bsdf = material.eval(wo, direction, normal, uv, objects);
+        if bsdf == Vec3::ZERO {
+            return Vec3::ZERO;
+        }
+
+        let visibility = Self::shadow_ray(pos, face_normal, direction).transmittance(f32::INFINITY, scene_info, seed, objects);
+        if visibility == Vec3::ZERO {
+            return Vec3::ZERO;
+        }
+
+        //radiance over the pdf of uniform cone sampling is just the irradiance
+        let light_pdf = 1.0 / sun.solid_angle();
+        sun.irradiance
+            * visibility
+            * bsdf
+            * power_heuristic(light_pdf, material.pdf(wo, direction, normal, uv, objects))
+    }
+
+    /// Light the environment map sends towards `wo` off the surface at `pos`.
+    fn sample_environment<M: Material>(
+        pos: Vec3,
+        face_normal: Vec3,
+        wo: Vec3,
+        normal: Vec3,
+        uv: (f32, f32),
+        material: &M,
+        scene_info: &shared::SceneInfo,
+        seed: &mut u32,
+        objects: &ObjectInfo,
+    ) -> Vec3 {
+        let environment = Environment::new(scene_info, objects);
+        if !environment.enabled() {
+            return Vec3::ZERO;
+        }
+
+        let (direction, light_pdf) = environment.sample(seed);
+        if light_pdf <= 0.0 {
+            return Vec3::ZERO;
+        }
+        let bsdf = material.eval(wo, direction, normal, uv, objects);
+        if bsdf == Vec3::ZERO {
+            return Vec3::ZERO;
+        }
+
+        let visibility = Self::shadow_ray(pos, face_normal, direction).transmittance(f32::INFINITY, scene_info, seed, objects);
+        if visibility == Vec3::ZERO {
+            return Vec3::ZERO;
+        }
+
+        let weight = power_heuristic(light_pdf, material.pdf(wo, direction, normal, uv, objects));
+        environment.radiance(direction) * visibility * bsdf * (weight / light_pdf)
+    }
+
+    /// Light an emissive triangle, picked from the light buffer, sends towards `wo` off the
+    /// surface at `pos`.
+    fn sample_emitters<M: Material>(
+        pos: Vec3,
+        face_normal: Vec3,
+        wo: Vec3,
+        normal: Vec3,
+        uv: (f32, f32),
+        material: &M,
+        scene_info: &shared::SceneInfo,
+        seed: &mut u32,
+        objects: &ObjectInfo,
+    ) -> Vec3 {
+        if scene_info.num_lights == 0 {
+            return Vec3::ZERO;
+        }
+
+        let light = sample_light_triangle(pos, normal, seed, objects);
+        let to_light = light.position - pos;
+        let distance = to_light.length();
+        let direction = to_light / distance;
+        let cos_light = light.normal.dot(direction).abs();
+        if light.pdf <= 0.0 || cos_light <= 0.0 {
+            return Vec3::ZERO;
+        }
+
+        let bsdf = material.eval(wo, direction, normal, uv, objects);
+        if bsdf == Vec3::ZERO {
+            return Vec3::ZERO;
+        }
+
+        let light_material = get_material(light.instance_id, objects);
+        let emission = PrincipledSurface::new(light_material, light.uv, objects).emission(
+            -direction,
+            light.normal,
+            light.uv,
+            objects,
+        );
+        if emission == Vec3::ZERO {
+            return Vec3::ZERO;
+        }
+
+        //lights don't cast shadows, so the whole way there can be checked
+        let visibility = Self::shadow_ray(pos, face_normal, direction).transmittance(distance, scene_info, seed, objects);
+        if visibility == Vec3::ZERO {
+            return Vec3::ZERO;
+        }
+
+        let light_pdf = light.pdf * distance * distance / cos_light;
+        let weight = power_heuristic(light_pdf, material.pdf(wo, direction, normal, uv, objects));
+        emission * visibility * bsdf * (weight / light_pdf)
+    }
+
+    /// Returns true if anything that casts shadows lies along the ray before `t_max`.
+    /// Unlike [`Ray::trace_ray`] this stops at the first hit instead of looking for the closest one.
+    pub fn occluded(
+        &self,
+        t_max: f32,
+        scene_info: &shared::SceneInfo,
+        objects: &ObjectInfo,
+    ) -> bool {
+        for i in 0..scene_info.num_instances as usize {
+            if !get_casts_shadow(i as u32, objects) {
+                continue;
+            }
+
+            let instance = &objects.instance_buffer[i];
+            let object = &objects.object_buffer[instance.object_id as usize];
+
+            let mesh = Mesh {
+                verts: objects.vertex_buffer,
+                tris: objects.triangle_buffer,
+                bvh_buffer: objects.bvh_buffer,
+                material_id: instance.material_id,
+                bvh_root: object.bvh_root,
+            };
+            let inverse_matrix = instance.transform.inverse();
+            let ray = Ray {
+                pos: inverse_matrix.transform_point3(self.pos),
+                orientation: inverse_matrix.transform_vector3(self.orientation),
+            };
+
+            if mesh.occluded(&ray, (f32::EPSILON, t_max), get_backface_culling(i as u32, objects)) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// How much light gets along the ray up to `t_max`, zero if anything casting shadows is in
+    /// the way and otherwise what the volumes it goes through let pass.
+    /// Volumes are expected to be closed meshes.
+    pub fn transmittance(
+        &self,
+        t_max: f32,
+        scene_info: &shared::SceneInfo,
+        seed: &mut u32,
+        objects: &ObjectInfo,
+    ) -> Vec3 {
+        if self.occluded(t_max, scene_info, objects) {
+            return Vec3::ZERO;
+        }
 
-        *self = mat_return.new_ray;
-        *color = mat_return.next_color;
+        let mut optical_depth = Vec3::ZERO;
+        let mut grid_transmittance = 1.0;
+        for i in 0..scene_info.num_instances as usize {
+            let material = get_material(i as u32, objects);
+            if material.model != MaterialModel::Volume || material.density <= 0.0 {
+                continue;
+            }
+
+            let instance = &objects.instance_buffer[i];
+            let object = &objects.object_buffer[instance.object_id as usize];
 
-        mat_return.ray_return_state
+            let mesh = Mesh {
+                verts: objects.vertex_buffer,
+                tris: objects.triangle_buffer,
+                bvh_buffer: objects.bvh_buffer,
+                material_id: instance.material_id,
+                bvh_root: object.bvh_root,
+            };
+            let inverse_matrix = instance.transform.inverse();
+            let ray = Ray {
+                pos: inverse_matrix.transform_point3(self.pos),
+                orientation: inverse_matrix.transform_vector3(self.orientation),
+            };
+
+            let medium = Medium::new(i as u32, material, 1.0, Vec3::ZERO);
+
+            //every crossing of the bounds switches between outside and inside, if the first
+            //one leaves the ray started inside. Looking past t_max tells if it ends inside.
+            let mut length = 0.0;
+            let mut entered_at = 0.0;
+            let mut inside = true;
+            let mut t_min = f32::EPSILON;
+            for _ in 0..MAX_VOLUME_CROSSINGS {
+                let mut record = HitRecord::new();
+                mesh.hit(&ray, (t_min, f32::INFINITY), &mut record, i as u32, false);
+                if record.t == f32::INFINITY {
+                    break;
+                }
+
+                let triangle = objects.triangle_buffer[record.triangle_id as usize];
+                let a = objects.vertex_buffer[triangle.0 as usize].pos;
+                let b = objects.vertex_buffer[triangle.1 as usize].pos;
+                let c = objects.vertex_buffer[triangle.2 as usize].pos;
+                let leaving = (b - a).cross(c - a).dot(ray.orientation) > 0.0;
+                if leaving && inside {
+                    let exited_at = record.t.min(t_max);
+                    if medium.grid != NO_DENSITY_GRID {
+                        grid_transmittance *= GridMedium::new(&medium, objects)
+                            .transmittance(self.pos, self.orientation, entered_at, exited_at, seed, objects);
+                    } else {
+                        length += exited_at - entered_at;
+                    }
+                } else if !leaving {
+                    entered_at = record.t;
+                }
+                inside = !leaving;
+                if record.t >= t_max {
+                    break;
+                }
+                t_min = record.t + SHADOW_RAY_OFFSET;
+            }
+            optical_depth += medium.extinction() * length;
+        }
+        exp(-optical_depth) * grid_transmittance
     }
 
     pub fn get_color(
@@ -179,7 +693,9 @@ impl Ray {
         scene_info: &shared::SceneInfo,
         objects: &ObjectInfo,
     ) -> Vec3 {
-        let mut color = Vec3::new(1.0, 1.0, 1.0);
+        let mut path = Path::new(data.color_mode, &mut rng_seed);
+        let mut scatter = Scatter::CAMERA;
+        let mut media = MediumStack::AIR;
 
         let mut vec = claculate_vec_dir_from_cam(
             data,
@@ -187,22 +703,79 @@ impl Ray {
                 pix_x as f32 + rand_float(&mut rng_seed, (0.0, 1.0)),
                 pix_y as f32 + rand_float(&mut rng_seed, (0.0, 1.0)),
             ),
+            &mut rng_seed,
         );
+        if vec.orientation == Vec3::ZERO {
+            return Vec3::ZERO;
+        }
         vec.normalize();
 
-        for _ in 0..data.depth {
-            //depth
-            let ray_return = vec.trace_ray(scene_info, &mut rng_seed, data, &mut color, objects);
+        let mut diffuse_bounces = 0;
+        let mut specular_bounces = 0;
+        let mut transmission_bounces = 0;
+        let mut volume_bounces = 0;
+        let mut subsurface_bounces = 0;
+
+        //steps of subsurface walks don't count towards the depth, they have a cap of their own
+        let mut depth = 0;
+        for _ in 0..data.depth + data.subsurface_depth {
+            if depth >= data.depth {
+                break;
+            }
+            let ray_return = vec.trace_ray(
+                scene_info,
+                &mut rng_seed,
+                data,
+                &mut path,
+                &mut scatter,
+                &mut media,
+                objects,
+            );
 
             match ray_return {
                 RayReturnState::Ray => {}
-                _ => {
-                    return color;
+                RayReturnState::Pass => {
+                    depth += 1;
+                    continue;
                 }
+                _ => break,
+            }
+
+            if scatter.lobe != Lobe::Subsurface {
+                depth += 1;
+            }
+            if scatter.lobe == Lobe::Diffuse {
+                diffuse_bounces += 1;
+            } else if scatter.lobe == Lobe::Specular {
+                specular_bounces += 1;
+            } else if scatter.lobe == Lobe::Transmission {
+                transmission_bounces += 1;
+            } else if scatter.lobe == Lobe::Volume {
+                volume_bounces += 1;
+            } else {
+                subsurface_bounces += 1;
+            }
+            if diffuse_bounces > data.diffuse_depth
+                || specular_bounces > data.specular_depth
+                || transmission_bounces > data.transmission_depth
+                || volume_bounces > data.volume_depth
+                || subsurface_bounces > data.subsurface_depth
+            {
+                break;
+            }
+
+            //russian roulette, dim paths are ended early and the survivors make up for them.
+            //walks keep going until their throughput actually drops, they need hundreds of steps
+            if depth >= data.min_depth {
+                let cap = if scatter.lobe == Lobe::Subsurface { 1.0 } else { 0.95 };
+                let survival = path.max_throughput().min(cap);
+                if rand_float(&mut rng_seed, (0.0, 1.0)) >= survival {
+                    break;
+                }
+                path.scale_throughput(1.0 / survival);
             }
         }
-        //never finished bouncing
-        Vec3::default()
+        path.rgb()
     }
 
     pub(super) fn hits_bounding(&self, bounding_box: &BoundingBox) -> f32 {
@@ -229,16 +802,137 @@ impl Ray {
     }
 }
 
+fn get_material<'a>(instance_id: u32, objects: &ObjectInfo<'a>) -> &'a PrincipledMaterial {
+    let instance = &objects.instance_buffer[instance_id as usize];
+    &objects.material_buffer[instance.material_id as usize]
+}
+
 fn get_backface_culling(
     instance_id: u32,
+    objects: &ObjectInfo,
 ) -> bool {
-    if instance_id == 0 {
-        MATERIAL_0.backface_culling()
-    } else if instance_id == 1 {
-        MATERIAL_1.backface_culling()
-    } else if instance_id == 2 {
-        MATERIAL_2.backface_culling()
+    let material = get_material(instance_id, objects);
+    if material.model == MaterialModel::Normals {
+        NORMAL_MATERIAL.backface_culling()
+    } else if material.model == MaterialModel::Uv {
+        UV_MATERIAL.backface_culling()
     } else {
-        true // Default to true for other materials
+        material.backface_culling()
+    }
+}
+
+fn get_casts_shadow(
+    instance_id: u32,
+    objects: &ObjectInfo,
+) -> bool {
+    let material = get_material(instance_id, objects);
+    if material.model == MaterialModel::Normals {
+        NORMAL_MATERIAL.casts_shadow()
+    } else if material.model == MaterialModel::Uv {
+        UV_MATERIAL.casts_shadow()
+    } else {
+        material.casts_shadow()
+    }
+}
+
+/// Power heuristic for combining two sampling strategies, the weight of the first one.
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let pdf_2 = pdf * pdf;
+    let other_pdf_2 = other_pdf * other_pdf;
+    if pdf_2 + other_pdf_2 <= 0.0 {
+        return 0.0;
+    }
+    pdf_2 / (pdf_2 + other_pdf_2)
+}
+
+/// Weight of a light found by following the bsdf, the light itself having been sampled with
+/// `light_pdf` at the last bounce.
+fn light_hit_weight(scatter_pdf: f32, light_pdf: f32) -> f32 {
+    if scatter_pdf <= 0.0 {
+        1.0
+    } else {
+        power_heuristic(scatter_pdf, light_pdf)
+    }
+}
+
+/// Material at a hit point: one of the debug views, or a principled material with its textures
+/// looked up once, as lights sampled at the same point evaluate it again.
+struct HitMaterial {
+    model: MaterialModel,
+    surface: PrincipledSurface,
+}
+
+impl HitMaterial {
+    /// `outside_ior` is the ior of the medium on the other side of the surface.
+    fn new(
+        material: &PrincipledMaterial,
+        uv: (f32, f32),
+        ior: f32,
+        outside_ior: f32,
+        objects: &ObjectInfo,
+    ) -> Self {
+        HitMaterial {
+            model: material.model,
+            surface: PrincipledSurface::new(material, uv, objects).relative_ior(ior, outside_ior),
+        }
+    }
+}
+
+impl Material for HitMaterial {
+    fn emission(&self, wo: Vec3, normal: Vec3, uv: (f32, f32), objects: &ObjectInfo) -> Vec3 {
+        if self.model == MaterialModel::Normals {
+            NORMAL_MATERIAL.emission(wo, normal, uv, objects)
+        } else if self.model == MaterialModel::Uv {
+            UV_MATERIAL.emission(wo, normal, uv, objects)
+        } else {
+            self.surface.emission(wo, normal, uv, objects)
+        }
+    }
+
+    fn sample(
+        &self,
+        wo: Vec3,
+        normal: Vec3,
+        uv: (f32, f32),
+        seed: &mut u32,
+        objects: &ObjectInfo,
+    ) -> BsdfSample {
+        if self.model == MaterialModel::Normals {
+            NORMAL_MATERIAL.sample(wo, normal, uv, seed, objects)
+        } else if self.model == MaterialModel::Uv {
+            UV_MATERIAL.sample(wo, normal, uv, seed, objects)
+        } else {
+            self.surface.sample(wo, normal, uv, seed, objects)
+        }
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, normal: Vec3, uv: (f32, f32), objects: &ObjectInfo) -> Vec3 {
+        if self.model == MaterialModel::Normals {
+            NORMAL_MATERIAL.eval(wo, wi, normal, uv, objects)
+        } else if self.model == MaterialModel::Uv {
+            UV_MATERIAL.eval(wo, wi, normal, uv, objects)
+        } else {
+            self.surface.eval(wo, wi, normal, uv, objects)
+        }
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, normal: Vec3, uv: (f32, f32), objects: &ObjectInfo) -> f32 {
+        if self.model == MaterialModel::Normals {
+            NORMAL_MATERIAL.pdf(wo, wi, normal, uv, objects)
+        } else if self.model == MaterialModel::Uv {
+            UV_MATERIAL.pdf(wo, wi, normal, uv, objects)
+        } else {
+            self.surface.pdf(wo, wi, normal, uv, objects)
+        }
+    }
+
+    fn absorption(&self) -> Vec3 {
+        if self.model == MaterialModel::Normals {
+            NORMAL_MATERIAL.absorption()
+        } else if self.model == MaterialModel::Uv {
+            UV_MATERIAL.absorption()
+        } else {
+            self.surface.absorption()
+        }
     }
 }
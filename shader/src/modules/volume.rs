@@ -0,0 +1,138 @@
+use super::medium::Medium;
+use super::rand_float;
+use super::ObjectInfo;
+use shared::glam::{Affine3A, Vec3};
+use shared::DensityGrid;
+#[allow(unused_imports)] //actually used for .floor because we don't allow std
+use spirv_std::num_traits::Float;
+
+//tentative collisions tracking follows before giving up, dense grids need a lot of them
+const MAX_TRACKING_STEPS: u32 = 256;
+
+/// What [`GridMedium::sample_collision`] returns when it gave up before finding out.
+pub const TRACKING_LOST: f32 = -1.0;
+
+/// Density grid of a medium, placed over the bounding box of its mesh by the instance transform.
+/// The extinction of grid media is taken to be grey, the largest channel of theirs.
+pub struct GridMedium {
+    grid: DensityGrid,
+    to_local: Affine3A,
+    min: Vec3,
+    size: Vec3,
+    /// extinction where the grid is 1
+    extinction: f32,
+}
+
+impl GridMedium {
+    pub fn new(medium: &Medium, objects: &ObjectInfo) -> Self {
+        let instance = &objects.instance_buffer[medium.instance_id as usize];
+        let root = objects.object_buffer[instance.object_id as usize].bvh_root;
+        let bounds = &objects.bvh_buffer[root as usize].bounding_box;
+        GridMedium {
+            grid: objects.density_grids[medium.grid as usize],
+            to_local: instance.transform.inverse(),
+            min: bounds.min,
+            size: bounds.max - bounds.min,
+            extinction: medium.extinction().max_element(),
+        }
+    }
+
+    fn voxel(&self, x: i32, y: i32, z: i32, objects: &ObjectInfo) -> f32 {
+        let x = x.clamp(0, self.grid.width as i32 - 1) as u32;
+        let y = y.clamp(0, self.grid.height as i32 - 1) as u32;
+        let z = z.clamp(0, self.grid.depth as i32 - 1) as u32;
+        objects.densities[(self.grid.offset + x + self.grid.width * (y + self.grid.height * z)) as usize]
+    }
+
+    /// Extinction at `pos` in world space, trilinearly filtered and zero outside of the grid.
+    pub fn extinction(&self, pos: Vec3, objects: &ObjectInfo) -> f32 {
+        let uvw = (self.to_local.transform_point3(pos) - self.min) / self.size;
+        if uvw.min_element() < 0.0 || uvw.max_element() > 1.0 {
+            return 0.0;
+        }
+
+        //voxel centers sit at half integers
+        let resolution = Vec3::new(self.grid.width as f32, self.grid.height as f32, self.grid.depth as f32);
+        let p = uvw * resolution - 0.5;
+        let base = p.floor();
+        let t = p - base;
+        let (x, y, z) = (base.x as i32, base.y as i32, base.z as i32);
+
+        let mut density = 0.0;
+        for corner in 0..8 {
+            let dx = corner & 1;
+            let dy = (corner >> 1) & 1;
+            let dz = (corner >> 2) & 1;
+            let weight = (if dx == 1 { t.x } else { 1.0 - t.x })
+                * (if dy == 1 { t.y } else { 1.0 - t.y })
+                * (if dz == 1 { t.z } else { 1.0 - t.z });
+            density += weight * self.voxel(x + dx, y + dy, z + dz, objects);
+        }
+        density * self.extinction
+    }
+
+    fn majorant(&self) -> f32 {
+        self.grid.max_density * self.extinction
+    }
+
+    /// Delta tracking, distance along the ray to where it really collides with the medium
+    /// before `t_max`, infinity when it gets through. Runs out of steps only in very dense
+    /// grids and returns [`TRACKING_LOST`] then, the path has to end there rather than
+    /// pretend it got through.
+    /// Based on: Woodcock et al., "Techniques used in the GEM code for Monte Carlo neutronics calculations"
+    pub fn sample_collision(
+        &self,
+        pos: Vec3,
+        direction: Vec3,
+        t_max: f32,
+        seed: &mut u32,
+        objects: &ObjectInfo,
+    ) -> f32 {
+        let majorant = self.majorant();
+        if majorant <= 0.0 {
+            return f32::INFINITY;
+        }
+
+        let mut t = 0.0;
+        for _ in 0..MAX_TRACKING_STEPS {
+            t -= (1.0 - rand_float(seed, (0.0, 1.0))).max(1e-6).ln() / majorant;
+            if t >= t_max {
+                return f32::INFINITY;
+            }
+            //the rest of the majorant is made of null particles the ray just goes through
+            if rand_float(seed, (0.0, 1.0)) * majorant < self.extinction(pos + direction * t, objects) {
+                return t;
+            }
+        }
+        TRACKING_LOST
+    }
+
+    /// Ratio tracking, unbiased estimate of how much light gets through between `t_min` and
+    /// `t_max` along the ray, nothing when it runs out of steps.
+    /// Based on: Novák et al., "Residual Ratio Tracking for Estimating Attenuation in Participating Media"
+    pub fn transmittance(
+        &self,
+        pos: Vec3,
+        direction: Vec3,
+        t_min: f32,
+        t_max: f32,
+        seed: &mut u32,
+        objects: &ObjectInfo,
+    ) -> f32 {
+        let majorant = self.majorant();
+        if majorant <= 0.0 {
+            return 1.0;
+        }
+
+        let mut transmittance = 1.0;
+        let mut t = t_min;
+        for _ in 0..MAX_TRACKING_STEPS {
+            t -= (1.0 - rand_float(seed, (0.0, 1.0))).max(1e-6).ln() / majorant;
+            if t >= t_max {
+                return transmittance;
+            }
+            transmittance *= 1.0 - self.extinction(pos + direction * t, objects) / majorant;
+        }
+        0.0
+    }
+}
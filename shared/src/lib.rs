@@ -5,7 +5,7 @@
 
 
 use core::f32::consts::PI;
-use glam::{vec3, Vec3};
+use glam::{vec3, Vec2, Vec3, Vec4};
 
 pub use spirv_std::glam;
 
@@ -63,18 +63,359 @@ pub fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
     x * x * (3.0 - 2.0 * x)
 }
 
+const DEPOLARIZATION_FACTOR: f32 = 0.035;
+const MIE_COEFFICIENT: f32 = 0.005;
+const MIE_DIRECTIONAL_G: f32 = 0.8;
+const MIE_K_COEFFICIENT: Vec3 = Vec3::new(0.686, 0.678, 0.666);
+const MIE_V: f32 = 4.0;
+const MIE_ZENITH_LENGTH: f32 = 1.25e3;
+const NUM_MOLECULES: f32 = 2.542e25f32;
+const PRIMARIES: Vec3 = Vec3::new(6.8e-7f32, 5.5e-7f32, 4.5e-7f32);
+const RAYLEIGH: f32 = 1.0;
+const RAYLEIGH_ZENITH_LENGTH: f32 = 8.4e3;
+const REFRACTIVE_INDEX: f32 = 1.0003;
+const SUN_INTENSITY_FACTOR: f32 = 1000.0;
+const SUN_INTENSITY_FALLOFF_STEEPNESS: f32 = 1.5;
+/// Brings the output of [`sky`] into the same range as the default sun intensity.
+const SKY_RADIANCE_SCALE: f32 = 0.004;
+
+fn total_rayleigh(lambda: Vec3) -> Vec3 {
+    (8.0 * PI.powf(3.0)
+        * (REFRACTIVE_INDEX.powf(2.0) - 1.0).powf(2.0)
+        * (6.0 + 3.0 * DEPOLARIZATION_FACTOR))
+        / (3.0 * NUM_MOLECULES * pow(lambda, 4.0) * (6.0 - 7.0 * DEPOLARIZATION_FACTOR))
+}
+
+fn total_mie(lambda: Vec3, k: Vec3, t: f32) -> Vec3 {
+    let c = 0.2 * t * 10e-18;
+    0.434 * c * PI * pow((2.0 * PI) / lambda, MIE_V - 2.0) * k
+}
+
+fn rayleigh_phase(cos_theta: f32) -> f32 {
+    (3.0 / (16.0 * PI)) * (1.0 + cos_theta.powf(2.0))
+}
+
+fn henyey_greenstein_phase(cos_theta: f32, g: f32) -> f32 {
+    (1.0 / (4.0 * PI)) * ((1.0 - g.powf(2.0)) / (1.0 - 2.0 * g * cos_theta + g.powf(2.0)).powf(1.5))
+}
+
+fn sun_intensity(zenith_angle_cos: f32) -> f32 {
+    let cutoff_angle = PI / 1.95; // Earth shadow hack
+    SUN_INTENSITY_FACTOR
+        * 0.0f32.max(
+            1.0 - (-((cutoff_angle - acos_approx(zenith_angle_cos))
+                / SUN_INTENSITY_FALLOFF_STEEPNESS))
+                .exp(),
+        )
+}
+
+/// Rayleigh and Mie scattering coefficients for the given sun direction and turbidity.
+fn scattering_coefficients(sun_direction: Vec3, turbidity: f32) -> (Vec3, Vec3) {
+    let sunfade = 1.0 - saturate(1.0 - sun_direction.y.exp());
+    let rayleigh_coefficient = RAYLEIGH - (1.0 * (1.0 - sunfade));
+    let beta_r = total_rayleigh(PRIMARIES) * rayleigh_coefficient;
+    let beta_m = total_mie(PRIMARIES, MIE_K_COEFFICIENT, turbidity) * MIE_COEFFICIENT;
+    (beta_r, beta_m)
+}
+
+/// Combined extinction along `dir` through the whole atmosphere.
+fn extinction(dir: Vec3, beta_r: Vec3, beta_m: Vec3) -> Vec3 {
+    // Optical length, cutoff angle at 90 to avoid singularity
+    let zenith_angle = acos_approx(dir.y.max(0.0));
+    let denom = zenith_angle.cos() + 0.15 * (93.885 - ((zenith_angle * 180.0) / PI)).powf(-1.253);
+
+    let s_r = RAYLEIGH_ZENITH_LENGTH / denom;
+    let s_m = MIE_ZENITH_LENGTH / denom;
+
+    exp(-(beta_r * s_r + beta_m * s_m))
+}
+
+/// Radiance of the clear sky seen along `dir`, without the sun disk itself.
+/// Both directions are expected to be normalized, with +y pointing up.
+pub fn sky(dir: Vec3, sun_direction: Vec3, turbidity: f32) -> Vec3 {
+    let up = vec3(0.0, 1.0, 0.0);
+    let (beta_r, beta_m) = scattering_coefficients(sun_direction, turbidity);
+    let fex = extinction(dir, beta_r, beta_m);
+
+    // In-scattering
+    let cos_theta = dir.dot(sun_direction);
+    let beta_r_theta = beta_r * rayleigh_phase(cos_theta * 0.5 + 0.5);
+    let beta_m_theta = beta_m * henyey_greenstein_phase(cos_theta, MIE_DIRECTIONAL_G);
+    let sun_e = sun_intensity(sun_direction.dot(up));
+    let scattered = sun_e * ((beta_r_theta + beta_m_theta) / (beta_r + beta_m));
+    let mut lin = pow(scattered * (Vec3::splat(1.0) - fex), 1.5);
+    lin *= Vec3::splat(1.0).lerp(
+        pow(scattered * fex, 0.5),
+        saturate((1.0 - up.dot(sun_direction)).powf(5.0)),
+    );
+
+    // Faint glow so the night sky isn't pitch black
+    let l0 = 0.1 * fex;
+
+    (lin + l0) * SKY_RADIANCE_SCALE
+}
+
+/// How much of the sunlight makes it through the atmosphere, relative to the sun standing at
+/// the zenith. Reddens and fades the sun as it approaches the horizon.
+pub fn sun_transmittance(sun_direction: Vec3, turbidity: f32) -> Vec3 {
+    let up = vec3(0.0, 1.0, 0.0);
+    let (beta_r, beta_m) = scattering_coefficients(sun_direction, turbidity);
+    let fex = extinction(sun_direction, beta_r, beta_m) / extinction(up, beta_r, beta_m);
+    fex * (sun_intensity(sun_direction.y) / sun_intensity(1.0))
+}
+
 #[derive(Copy, Clone, PartialEq)]
 #[repr(C)]
 pub struct CamData {
+    /// hard cap on bounces, russian roulette usually ends paths well before it
     pub depth: u32,
     pub transform: glam::Affine3A,
     pub canvas_width: u32,
     pub canvas_height: u32,
+    /// horizontal, in degrees, of the perspective and fisheye projections
     pub fov: f32,
     pub frame: u32,
     pub debug_number: u32,
     pub debug_information: DebugInformation,
     pub frames_without_move: f32,
+    /// bounces before russian roulette starts ending paths
+    pub min_depth: u32,
+    /// caps on bounces off each kind of lobe, paths past one of them end
+    pub diffuse_depth: u32,
+    pub specular_depth: u32,
+    pub transmission_depth: u32,
+    pub color_mode: ColorMode,
+    /// cap on scattering events inside media
+    pub volume_depth: u32,
+    /// cap on steps of subsurface random walks, which don't count towards `depth`
+    pub subsurface_depth: u32,
+    /// radius of the thin lens in world units, 0.0 is a pinhole with everything in focus
+    pub aperture_radius: f32,
+    /// distance along the view direction that is in focus
+    pub focus_distance: f32,
+    /// straight sided aperture with this many blades, below 3 it is round
+    pub aperture_blades: u32,
+    pub projection: Projection,
+    /// width of the view of the orthographic projection in world units
+    pub ortho_width: f32,
+    pub stereo: StereoLayout,
+    /// distance between the eyes of stereo renders in world units
+    pub interocular_distance: f32,
+    /// distance the eyes of stereo renders look at the same point from, infinity keeps them parallel
+    pub convergence_distance: f32,
+    pub exposure_mode: ExposureMode,
+    /// exposure value at iso 100 of manual exposure, see [`ev100`]
+    pub ev100: f32,
+    /// in stops, brightens the image in both modes
+    pub exposure_compensation: f32,
+    /// applied to what is displayed only, the accumulated radiance stays linear
+    pub tone_mapping: ToneMapping,
+    /// set by the app to match the swapchain
+    pub output_encoding: OutputEncoding,
+}
+
+impl CamData {
+    /// Ray through `(pix_x, pix_y)` in camera space, as origin and direction, z pointing forward.
+    /// Perspective and orthographic directions are one unit long along z, so distances along
+    /// them are depths. Pixels the projection doesn't cover get a zero direction.
+    pub fn view_ray(&self, (pix_x, pix_y): (f32, f32)) -> (Vec3, Vec3) {
+        let width = self.canvas_width as f32;
+        let height = self.canvas_height as f32;
+        //each eye gets half of the canvas, the left one on the left or on top
+        let (eye, pixel, size) = match self.stereo {
+            StereoLayout::Mono => (0.0, (pix_x, pix_y), (width, height)),
+            StereoLayout::SideBySide => {
+                let half = width / 2.0;
+                if pix_x < half {
+                    (-1.0, (pix_x, pix_y), (half, height))
+                } else {
+                    (1.0, (pix_x - half, pix_y), (half, height))
+                }
+            }
+            StereoLayout::OverUnder => {
+                let half = height / 2.0;
+                if pix_y < half {
+                    (-1.0, (pix_x, pix_y), (width, half))
+                } else {
+                    (1.0, (pix_x, pix_y - half), (width, half))
+                }
+            }
+        };
+
+        let (origin, direction) = self.projection_ray(pixel, size);
+        if eye == 0.0 || direction == Vec3::ZERO {
+            return (origin, direction);
+        }
+
+        let half_interocular = eye * self.interocular_distance / 2.0;
+        let offset = if self.projection == Projection::Equirectangular {
+            //omni-directional stereo, the eyes turn with the view around a circle, their
+            //distance shrinking towards the poles so looking up or down doesn't hurt
+            //Based on: Google, "Rendering Omni-directional Stereo Content"
+            let horizontal = Vec3::new(direction.z, 0.0, -direction.x);
+            horizontal * half_interocular
+        } else {
+            Vec3::new(half_interocular, 0.0, 0.0)
+        };
+        //both eyes look at the same point at the convergence distance
+        (
+            origin + offset,
+            direction - offset / self.convergence_distance,
+        )
+    }
+
+    /// Ray through `(pix_x, pix_y)` of an image `width` by `height` pixels large.
+    fn projection_ray(&self, (pix_x, pix_y): (f32, f32), (width, height): (f32, f32)) -> (Vec3, Vec3) {
+        let pix_x_frac = (pix_x / width) * 2.0 - 1.0;
+        let pix_y_frac = (pix_y / height) * 2.0 - 1.0;
+        let pix_y_frac_adjusted = pix_y_frac * (height / width);
+        let half_fov = (self.fov * PI / 180.0) / 2.0;
+
+        match self.projection {
+            Projection::Perspective => {
+                let edge_dist = half_fov.tan();
+                (
+                    Vec3::ZERO,
+                    Vec3::new(pix_x_frac * edge_dist, pix_y_frac_adjusted * edge_dist, 1.0),
+                )
+            }
+            Projection::Orthographic => {
+                let half_width = self.ortho_width / 2.0;
+                (
+                    Vec3::new(pix_x_frac * half_width, pix_y_frac_adjusted * half_width, 0.0),
+                    Vec3::Z,
+                )
+            }
+            Projection::Fisheye => {
+                //equidistant, the angle from the view direction grows linearly towards the edges
+                let radius = (pix_x_frac * pix_x_frac + pix_y_frac_adjusted * pix_y_frac_adjusted).sqrt();
+                let theta = radius * half_fov;
+                if theta > PI {
+                    return (Vec3::ZERO, Vec3::ZERO);
+                }
+                if radius <= 0.0 {
+                    return (Vec3::ZERO, Vec3::Z);
+                }
+                let sin_theta = theta.sin();
+                (
+                    Vec3::ZERO,
+                    Vec3::new(
+                        sin_theta * pix_x_frac / radius,
+                        sin_theta * pix_y_frac_adjusted / radius,
+                        theta.cos(),
+                    ),
+                )
+            }
+            Projection::Equirectangular => {
+                //the whole sphere regardless of fov, it fits a canvas twice as wide as high
+                let longitude = pix_x_frac * PI;
+                let latitude = pix_y_frac * PI / 2.0;
+                (
+                    Vec3::ZERO,
+                    Vec3::new(
+                        latitude.cos() * longitude.sin(),
+                        latitude.sin(),
+                        latitude.cos() * longitude.cos(),
+                    ),
+                )
+            }
+        }
+    }
+}
+
+/// Exposure value at iso 100 of a photographic exposure, `shutter_time` being in seconds.
+pub fn ev100(f_number: f32, shutter_time: f32, iso: f32) -> f32 {
+    (f_number * f_number / shutter_time * 100.0 / iso).log2()
+}
+
+/// Bins of the log luminance histogram auto exposure is metered from.
+pub const HISTOGRAM_BINS: u32 = 64;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ExposureMode {
+    Manual,
+    /// metered from the histogram of the previous frame
+    Auto,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ToneMapping {
+    /// clips everything brighter than 1.0
+    None,
+    Reinhard,
+    Filmic,
+    Aces,
+    Agx,
+}
+
+/// How the displayed image is encoded before it leaves the shader.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum OutputEncoding {
+    /// for swapchains that take srgb encoded values as they are
+    Srgb,
+    /// for srgb swapchains, which encode by themselves
+    Linear,
+}
+
+impl OutputEncoding {
+    /// What the shader writes into the output image for a tone mapped linear `value`.
+    pub fn encode(self, value: f32) -> f32 {
+        if self == OutputEncoding::Srgb {
+            srgb_encode(value)
+        } else {
+            value.clamp(0.0, 1.0)
+        }
+    }
+
+    /// What reaches the screen for a `value` the shader wrote, srgb swapchains encoding it on
+    /// the way in.
+    pub fn displayed(self, value: f32) -> f32 {
+        if self == OutputEncoding::Linear {
+            srgb_encode(value)
+        } else {
+            value.clamp(0.0, 1.0)
+        }
+    }
+}
+
+/// The piecewise srgb transfer function, not a plain 2.2 gamma.
+pub fn srgb_encode(value: f32) -> f32 {
+    let value = value.clamp(0.0, 1.0);
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Wavelength in nanometers the ior of materials is given at, also what rgb mode refracts with.
+pub const D_LINE_WAVELENGTH: f32 = 587.6;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ColorMode {
+    Rgb,
+    /// every path traces a few wavelengths instead, so dispersive materials split light
+    Spectral,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Projection {
+    Perspective,
+    Orthographic,
+    /// equidistant, `fov` can go up to a full 360 degrees
+    Fisheye,
+    /// latitude and longitude of the whole sphere around the camera
+    Equirectangular,
+}
+
+/// How the images for the two eyes share the canvas, which the app makes twice as wide or high
+/// for them so each eye keeps the full resolution. With the equirectangular projection the eyes
+/// are omni-directional stereo ones.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum StereoLayout {
+    Mono,
+    SideBySide,
+    OverUnder,
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
@@ -89,7 +430,37 @@ pub struct SceneInfo {
     pub num_instances: u32,
     pub num_bvh_nodes: u32,
     pub num_triangles: u32,
+    /// irradiance the sun delivers to a surface facing it
+    pub sun_intensity: f32,
+    /// direction the sunlight travels in, so it points away from the sun
     pub sun_orientation: Vec3,
+    #[cfg(not(target_arch = "spirv"))]
+    pub padding_1: [u8; 4],
+    pub sun_color: Vec3,
+    #[cfg(not(target_arch = "spirv"))]
+    pub padding_2: [u8; 4],
+    /// in degrees
+    pub sun_angular_diameter: f32,
+    /// haziness of the sky, 2.0 is a clear day and 10.0 is very hazy
+    pub sky_turbidity: f32,
+    pub sky_intensity: f32,
+    #[cfg(not(target_arch = "spirv"))]
+    pub padding_3: [u8; 4],
+    /// color of the ground below the horizon
+    pub ground_albedo: Vec3,
+    #[cfg(not(target_arch = "spirv"))]
+    pub padding_4: [u8; 4],
+    /// 0 when no environment map is loaded and the sky is used instead
+    pub environment_width: u32,
+    pub environment_height: u32,
+    /// rotation of the environment map around the y axis, in degrees
+    pub environment_rotation: f32,
+    pub environment_intensity: f32,
+    /// size of every layer in the texture array
+    pub texture_width: u32,
+    pub texture_height: u32,
+    /// number of entries in the light buffer, the light tree is empty without any
+    pub num_lights: u32,
 }
 
 pub struct Sphere {
@@ -115,13 +486,25 @@ pub struct Vertex {
     pub pos: Vec3,
     #[cfg(not(target_arch = "spirv"))]
     _padding: [u8; 4],
+    /// zero if the mesh has no normals, the face normal is used instead
+    pub normal: Vec3,
+    #[cfg(not(target_arch = "spirv"))]
+    _padding_2: [u8; 4],
+    /// xyz is the tangent, w the sign of the bitangent, `bitangent = w * normal.cross(tangent)`
+    pub tangent: Vec4,
+    pub uv: Vec2,
 }
 
 impl Vertex {
-    pub fn new(pos: Vec3) -> Self {
+    pub fn new(pos: Vec3, normal: Vec3, tangent: Vec4, uv: Vec2) -> Self {
         #[cfg(target_arch = "spirv")]
         {
-            Vertex { pos }
+            Vertex {
+                pos,
+                normal,
+                tangent,
+                uv,
+            }
         }
 
         #[cfg(not(target_arch = "spirv"))]
@@ -129,6 +512,10 @@ impl Vertex {
             Vertex {
                 pos,
                 _padding: [0; 4],
+                normal,
+                _padding_2: [0; 4],
+                tangent,
+                uv,
             }
         }
     }
@@ -136,7 +523,329 @@ impl Vertex {
 
 impl Clone for Vertex {
     fn clone(&self) -> Self {
-        Self::new(self.pos)
+        Self::new(self.pos, self.normal, self.tangent, self.uv)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[repr(u32)]
+pub enum WrapMode {
+    Repeat = 0,
+    Clamp = 1,
+    Mirror = 2,
+}
+
+/// Reference from a material into the texture array.
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct TextureSlot {
+    /// layer in the texture array, [`TextureSlot::NONE`] if the material isn't textured
+    pub index: u32,
+    pub wrap_mode: WrapMode,
+}
+
+impl TextureSlot {
+    pub const NONE: TextureSlot = TextureSlot {
+        index: u32::MAX,
+        wrap_mode: WrapMode::Repeat,
+    };
+
+    pub const fn new(index: u32, wrap_mode: WrapMode) -> Self {
+        TextureSlot { index, wrap_mode }
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.index == u32::MAX
+    }
+}
+
+pub const NO_DENSITY_GRID: u32 = u32::MAX;
+
+/// How a material is shaded, `Normals` and `Uv` are debug views.
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[repr(u32)]
+pub enum MaterialModel {
+    Principled = 0,
+    /// colors every side of the cornell box by the direction it faces
+    Normals = 1,
+    Uv = 2,
+    /// the surface itself is invisible and only bounds the medium inside, for fog and smoke
+    Volume = 3,
+}
+
+/// Disney style material, parameters follow the glTF and Blender principled BSDF.
+/// All factors are in 0..1 unless noted otherwise.
+#[derive(Copy, Clone, Debug)]
+#[repr(C, align(16))]
+pub struct PrincipledMaterial {
+    /// linear rgb
+    pub base_color: Vec3,
+    #[cfg(not(target_arch = "spirv"))]
+    _padding: [u8; 4],
+    /// linear rgb, multiplied by `emission_strength`
+    pub emission: Vec3,
+    #[cfg(not(target_arch = "spirv"))]
+    _padding_2: [u8; 4],
+    /// linear rgb left of white light after travelling `absorption_distance` inside
+    pub absorption_color: Vec3,
+    #[cfg(not(target_arch = "spirv"))]
+    _padding_3: [u8; 4],
+    /// share of what the medium inside stops that it scatters instead of absorbing, linear rgb
+    pub scattering_albedo: Vec3,
+    #[cfg(not(target_arch = "spirv"))]
+    _padding_4: [u8; 4],
+    /// color light comes back out of the subsurface part with, linear rgb
+    pub subsurface_color: Vec3,
+    #[cfg(not(target_arch = "spirv"))]
+    _padding_5: [u8; 4],
+    /// mean free path inside for every channel, in scene units
+    pub subsurface_radius: Vec3,
+    #[cfg(not(target_arch = "spirv"))]
+    _padding_6: [u8; 4],
+    pub model: MaterialModel,
+    /// 0 is a dielectric, 1 a conductor tinted by `base_color`
+    pub metallic: f32,
+    pub roughness: f32,
+    /// reflectance of dielectrics at normal incidence, 0.5 is 4%
+    pub specular: f32,
+    /// tints dielectric reflections towards `base_color`
+    pub specular_tint: f32,
+    /// how much of the dielectric part lets light through instead of scattering it diffusely
+    pub transmission: f32,
+    pub ior: f32,
+    /// in scene units, see `absorption_color`
+    pub absorption_distance: f32,
+    /// where transmissive materials overlap, the inside of the one with the higher priority wins
+    pub priority: u32,
+    /// how little the ior changes with the wavelength in spectral mode, 0 turns it off
+    pub abbe_number: f32,
+    /// extinction coefficient of the medium inside, in 1 / scene units, 0 leaves it clear
+    pub density: f32,
+    /// henyey-greenstein g of the medium inside, -1 scatters everything back, 1 forward
+    pub anisotropy: f32,
+    /// index of a [`DensityGrid`] scaling `density` through the inside, [`NO_DENSITY_GRID`]
+    /// keeps it constant
+    pub density_grid: u32,
+    /// how much of the diffuse part is scattered below the surface instead
+    pub subsurface: f32,
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    /// retroreflective rim for cloth
+    pub sheen: f32,
+    pub sheen_tint: f32,
+    /// unbounded
+    pub emission_strength: f32,
+    pub bump_strength: f32,
+    /// multiplies `base_color`, srgb
+    pub base_color_texture: TextureSlot,
+    /// green channel multiplies `roughness`, blue `metallic`, same as in glTF
+    pub metallic_roughness_texture: TextureSlot,
+    /// multiplies `emission`, srgb
+    pub emission_texture: TextureSlot,
+    /// tangent space normal map, takes precedence over `bump_texture`
+    pub normal_texture: TextureSlot,
+    /// height map, red channel
+    pub bump_texture: TextureSlot,
+}
+
+impl PrincipledMaterial {
+    /// A rough white plastic, change it with the builder methods.
+    pub const fn new() -> Self {
+        PrincipledMaterial {
+            base_color: Vec3::new(0.8, 0.8, 0.8),
+            #[cfg(not(target_arch = "spirv"))]
+            _padding: [0; 4],
+            emission: Vec3::ONE,
+            #[cfg(not(target_arch = "spirv"))]
+            _padding_2: [0; 4],
+            absorption_color: Vec3::ONE,
+            #[cfg(not(target_arch = "spirv"))]
+            _padding_3: [0; 4],
+            scattering_albedo: Vec3::ONE,
+            #[cfg(not(target_arch = "spirv"))]
+            _padding_4: [0; 4],
+            subsurface_color: Vec3::new(0.8, 0.8, 0.8),
+            #[cfg(not(target_arch = "spirv"))]
+            _padding_5: [0; 4],
+            subsurface_radius: Vec3::new(1.0, 0.2, 0.1),
+            #[cfg(not(target_arch = "spirv"))]
+            _padding_6: [0; 4],
+            model: MaterialModel::Principled,
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            specular_tint: 0.0,
+            transmission: 0.0,
+            ior: 1.5,
+            absorption_distance: 1.0,
+            priority: 0,
+            abbe_number: 0.0,
+            density: 0.0,
+            anisotropy: 0.0,
+            density_grid: NO_DENSITY_GRID,
+            subsurface: 0.0,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.03,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            emission_strength: 0.0,
+            bump_strength: 0.0,
+            base_color_texture: TextureSlot::NONE,
+            metallic_roughness_texture: TextureSlot::NONE,
+            emission_texture: TextureSlot::NONE,
+            normal_texture: TextureSlot::NONE,
+            bump_texture: TextureSlot::NONE,
+        }
+    }
+
+    pub const fn base_color(mut self, base_color: Vec3) -> Self {
+        self.base_color = base_color;
+        self
+    }
+
+    pub const fn emission(mut self, emission: Vec3, strength: f32) -> Self {
+        self.emission = emission;
+        self.emission_strength = strength;
+        self
+    }
+
+    pub const fn model(mut self, model: MaterialModel) -> Self {
+        self.model = model;
+        self
+    }
+
+    pub const fn metallic(mut self, metallic: f32) -> Self {
+        self.metallic = metallic;
+        self
+    }
+
+    pub const fn roughness(mut self, roughness: f32) -> Self {
+        self.roughness = roughness;
+        self
+    }
+
+    pub const fn specular(mut self, specular: f32, tint: f32) -> Self {
+        self.specular = specular;
+        self.specular_tint = tint;
+        self
+    }
+
+    pub const fn transmission(mut self, transmission: f32, ior: f32) -> Self {
+        self.transmission = transmission;
+        self.ior = ior;
+        self
+    }
+
+    /// Tints light travelling through the inside of transmissive materials, so that after
+    /// `distance` only `color` of it is left. Thicker parts get darker and more saturated.
+    pub const fn absorption(mut self, color: Vec3, distance: f32) -> Self {
+        self.absorption_color = color;
+        self.absorption_distance = distance;
+        self
+    }
+
+    /// Lets a liquid be modelled slightly overlapping its glass, the glass gets the higher
+    /// priority and the part of the liquid inside it is ignored.
+    pub const fn priority(mut self, priority: u32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Spreads the ior over the wavelengths, around 60 for crown glass, 55 for water and 44
+    /// for diamond. Lower numbers split light into more of a rainbow.
+    pub const fn dispersion(mut self, abbe_number: f32) -> Self {
+        self.abbe_number = abbe_number;
+        self
+    }
+
+    /// Fills the inside with a homogeneous medium. Use it with [`MaterialModel::Volume`] for
+    /// fog or smoke on its own, or on a transmissive material for murky glass and liquids.
+    pub const fn medium(mut self, albedo: Vec3, density: f32, anisotropy: f32) -> Self {
+        self.scattering_albedo = albedo;
+        self.density = density;
+        self.anisotropy = anisotropy;
+        self
+    }
+
+    /// Varies the density of the medium inside by a grid, in the order grids were added to the
+    /// scene. Grids only scale it, so the medium has to be set too.
+    pub const fn density_grid(mut self, grid: u32) -> Self {
+        self.density_grid = grid;
+        self
+    }
+
+    /// Light entering the diffuse part takes a random walk through the inside before coming
+    /// back out, for skin, wax and marble. Needs a closed mesh. `radius` is how far light gets
+    /// in every channel, red going the furthest in skin.
+    pub const fn subsurface(mut self, subsurface: f32, color: Vec3, radius: Vec3) -> Self {
+        self.subsurface = subsurface;
+        self.subsurface_color = color;
+        self.subsurface_radius = radius;
+        self
+    }
+
+    pub const fn clearcoat(mut self, clearcoat: f32, roughness: f32) -> Self {
+        self.clearcoat = clearcoat;
+        self.clearcoat_roughness = roughness;
+        self
+    }
+
+    pub const fn sheen(mut self, sheen: f32, tint: f32) -> Self {
+        self.sheen = sheen;
+        self.sheen_tint = tint;
+        self
+    }
+
+    pub const fn base_color_texture(mut self, texture: TextureSlot) -> Self {
+        self.base_color_texture = texture;
+        self
+    }
+
+    pub const fn metallic_roughness_texture(mut self, texture: TextureSlot) -> Self {
+        self.metallic_roughness_texture = texture;
+        self
+    }
+
+    pub const fn emission_texture(mut self, texture: TextureSlot) -> Self {
+        self.emission_texture = texture;
+        self
+    }
+
+    pub const fn normal_texture(mut self, texture: TextureSlot) -> Self {
+        self.normal_texture = texture;
+        self
+    }
+
+    pub const fn bump_texture(mut self, texture: TextureSlot, strength: f32) -> Self {
+        self.bump_texture = texture;
+        self.bump_strength = strength;
+        self
+    }
+
+    /// Brightness of the untextured emission, lights are picked proportionally to it times
+    /// their area. Zero for the debug views, which never emit.
+    pub fn emitted_luminance(&self) -> f32 {
+        if self.model != MaterialModel::Principled || self.emission_strength <= 0.0 {
+            return 0.0;
+        }
+        self.emission.dot(Vec3::new(0.2126, 0.7152, 0.0722)) * self.emission_strength
+    }
+
+    /// Ior at `wavelength` nanometers, `ior` itself being the one at the fraunhofer d line.
+    pub fn ior_at(&self, wavelength: f32) -> f32 {
+        if self.abbe_number <= 0.0 {
+            return self.ior;
+        }
+        //cauchy's equation n = a + b / λ², fitted through the d, F and C lines
+        let b = (self.ior - 1.0)
+            / (self.abbe_number * (1.0 / (486.1 * 486.1) - 1.0 / (656.3 * 656.3)));
+        self.ior + b * (1.0 / (wavelength * wavelength) - 1.0 / (D_LINE_WAVELENGTH * D_LINE_WAVELENGTH))
+    }
+}
+
+impl Default for PrincipledMaterial {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -161,9 +870,60 @@ pub struct Object {
     pub bvh_root: u32,
 }
 
+/// Dense voxel grid of densities, stretched over the bounding box of the mesh whose material
+/// uses it. The voxels are in the density buffer from `offset` on, x changing the fastest.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C, align(16))]
+pub struct DensityGrid {
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    pub offset: u32,
+    /// largest voxel, bounds the density for tracking
+    pub max_density: f32,
+}
+
+/// Triangle of an emissive instance, lights are sampled directly by picking one of these.
+/// The light buffer is sorted by instance and then triangle, so hits can be looked up.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C, align(16))]
+pub struct LightTriangle {
+    pub instance_id: u32,
+    /// index into the triangle buffer
+    pub triangle_id: u32,
+    /// way down the light tree to this triangle, bit `i` is set when the second child was
+    /// taken at depth `i`
+    pub tree_path: u32,
+}
+
+/// Node of the tree lights are picked with, bounding where the triangles below it are,
+/// which way they face and how much light they give off.
+#[derive(Debug)]
+#[repr(C, align(16))]
+pub struct LightNode {
+    pub bounding_box: BoundingBox,
+    /// axis of a cone holding the normal of every triangle below
+    pub axis: Vec3,
+    #[cfg(not(target_arch = "spirv"))]
+    pub padding: [u8; 4],
+    /// cosine of the half angle of that cone
+    pub cos_theta_o: f32,
+    /// cosine of how far past its normal a triangle still gives off light
+    pub cos_theta_e: f32,
+    /// summed area times emitted luminance
+    pub power: f32,
+    /// index into the light buffer for leaves
+    pub child_1_or_light: u32,
+    pub child_2: u32,
+    /// leaves hold a single triangle
+    pub mode: ChildTriangleMode,
+}
+
 pub struct Instance {
     pub transform: glam::Affine3A,
     pub object_id: u32,
+    /// index into the material buffer
+    pub material_id: u32,
 }
 
 #[derive(Debug)]
@@ -181,3 +941,19 @@ pub enum ChildTriangleMode {
     Children = 0,
     Triangles = 1,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear_values_reach_the_screen_srgb_encoded() {
+        let expected = [(0.0, 0), (0.0031308, 10), (0.18, 118), (0.5, 188), (1.0, 255)];
+        for encoding in [OutputEncoding::Srgb, OutputEncoding::Linear] {
+            for (linear, code) in expected {
+                let displayed = encoding.displayed(encoding.encode(linear));
+                assert_eq!((displayed * 255.0).round() as u8, code, "{:?} {}", encoding, linear);
+            }
+        }
+    }
+}